@@ -1,3 +1,3 @@
-cargo doc --features "multithreaded stderr"
+cargo doc --features multithreaded
 xdg-open target/doc/transaction_processor/index.html
 sleep 0.5 # prevent weird terminal messyness
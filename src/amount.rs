@@ -0,0 +1,316 @@
+use parse_display::{Display, FromStr};
+use std::{
+    fmt::{self, Formatter},
+    str::FromStr,
+};
+
+/// Number of decimal places kept by an [Amount].
+pub const DECIMALS: u32 = 4;
+/// Number of ten-thousandths in one unit.
+pub const SCALE: i64 = 10_i64.pow(DECIMALS);
+
+#[derive(Display, FromStr, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[display(style = "kebab-case")]
+/// Policies for rounding a decimal number to fewer decimal places.
+pub enum Rounding {
+    /// Round to the nearest, ties to the even neighbour. Also known as banker's rounding.
+    #[default]
+    HalfEven,
+    /// Round to the nearest, ties away from zero.
+    HalfUp,
+    /// Round to the nearest, ties towards zero.
+    HalfDown,
+    /// Drop the extra digits.
+    Down,
+}
+impl Rounding {
+    /// Returns true if a magnitude should be rounded away from zero.
+    ///
+    /// `kept_is_odd` tells whether the last digit that is kept is odd,
+    /// `first_dropped` is the first digit that is dropped and `rest_is_zero` whether all the following ones are zero.
+    fn rounds_up(self, kept_is_odd: bool, first_dropped: u8, rest_is_zero: bool) -> bool {
+        let half = first_dropped == 5 && rest_is_zero;
+        let above_half = first_dropped > 5 || (first_dropped == 5 && !rest_is_zero);
+        match self {
+            Rounding::HalfEven => above_half || (half && kept_is_odd),
+            Rounding::HalfUp => above_half || half,
+            Rounding::HalfDown => above_half,
+            Rounding::Down => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// A fixed-point decimal amount of money, stored as ten-thousandths.
+///
+/// All arithmetic is checked, an [Amount] never silently wraps or loses precision.
+pub struct Amount(i64);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+
+    /// Returns the [Amount] worth `ten_thousandths` ten-thousandths of a unit.
+    pub const fn from_raw(ten_thousandths: i64) -> Self {
+        Self(ten_thousandths)
+    }
+
+    /// Returns the number of ten-thousandths of a unit this [Amount] is worth.
+    pub const fn raw(self) -> i64 {
+        self.0
+    }
+
+    /// Returns `self + rhs` or [None] if the result is not representable.
+    pub fn checked_add(self, rhs: Amount) -> Option<Amount> {
+        self.0.checked_add(rhs.0).map(Amount)
+    }
+
+    /// Returns `self + rhs`, or the largest or smallest [Amount] if the result is not representable.
+    pub fn saturating_add(self, rhs: Amount) -> Amount {
+        Amount(self.0.saturating_add(rhs.0))
+    }
+
+    /// Returns `self - rhs` or [None] if the result is not representable.
+    pub fn checked_sub(self, rhs: Amount) -> Option<Amount> {
+        self.0.checked_sub(rhs.0).map(Amount)
+    }
+
+    /// Returns true if this [Amount] is smaller than zero.
+    pub fn is_negative(self) -> bool {
+        self.0 < 0
+    }
+
+    /// Parses a decimal number such as `-12.5` from `bytes`.
+    ///
+    /// Digits beyond the fourth decimal place are rounded half to even.
+    /// Returns [None] if `bytes` is not a plain decimal number or it doesn't fit in an [Amount].
+    pub fn parse(bytes: &[u8]) -> Option<Amount> {
+        Amount::parse_rounded(bytes, Rounding::HalfEven)
+    }
+
+    /// Same as [Amount::parse] but rounds digits beyond the fourth decimal place with `rounding`.
+    pub fn parse_rounded(bytes: &[u8], rounding: Rounding) -> Option<Amount> {
+        let (negative, bytes) = match bytes.split_first() {
+            Some((b'-', rest)) => (true, rest),
+            Some((b'+', rest)) => (false, rest),
+            _ => (false, bytes),
+        };
+        let (int, frac) = match bytes.iter().position(|&b| b == b'.') {
+            Some(dot) => (&bytes[..dot], &bytes[dot + 1..]),
+            None => (bytes, &[][..]),
+        };
+        if int.is_empty() && frac.is_empty() {
+            return None;
+        }
+        if !int.iter().chain(frac).all(u8::is_ascii_digit) {
+            return None;
+        }
+
+        let mut raw = 0i64;
+        for &digit in int {
+            raw = raw.checked_mul(10)?.checked_add((digit - b'0') as i64)?;
+        }
+        for i in 0..DECIMALS as usize {
+            let digit = frac.get(i).map_or(0, |d| d - b'0');
+            raw = raw.checked_mul(10)?.checked_add(digit as i64)?;
+        }
+
+        if let Some((&first, rest)) = frac.get(DECIMALS as usize..).and_then(<[u8]>::split_first) {
+            let rest_is_zero = rest.iter().all(|&d| d == b'0');
+            if rounding.rounds_up(raw % 2 == 1, first - b'0', rest_is_zero) {
+                raw = raw.checked_add(1)?;
+            }
+        }
+
+        Some(Amount(if negative { -raw } else { raw }))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The error returned when a string is not a valid [Amount].
+pub struct ParseAmountError;
+
+impl fmt::Display for ParseAmountError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid decimal amount")
+    }
+}
+
+impl std::error::Error for ParseAmountError {}
+
+impl FromStr for Amount {
+    type Err = ParseAmountError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Amount::parse(s.as_bytes()).ok_or(ParseAmountError)
+    }
+}
+
+impl fmt::Display for Amount {
+    /// Writes the shortest decimal representation of the [Amount], without trailing zeros.
+    ///
+    /// If a precision is specified, as in `{:.4}`, exactly that many decimal places are written,
+    /// rounding half to even if it is smaller than four.
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let sign = if self.is_negative() { "-" } else { "" };
+        let magnitude = self.0.unsigned_abs();
+
+        if let Some(precision) = f.precision() {
+            let kept = precision.min(DECIMALS as usize);
+            let divisor = 10u64.pow(DECIMALS - kept as u32);
+            let mut value = magnitude / divisor;
+            let dropped = magnitude % divisor;
+            if divisor > 1 {
+                let first_dropped = (dropped / (divisor / 10)) as u8;
+                let rest_is_zero = dropped.is_multiple_of(divisor / 10);
+                if Rounding::HalfEven.rounds_up(value % 2 == 1, first_dropped, rest_is_zero) {
+                    value += 1;
+                }
+            }
+            let unit = 10u64.pow(kept as u32);
+            let sign = if value == 0 { "" } else { sign };
+            write!(f, "{}{}", sign, value / unit)?;
+            if precision > 0 {
+                write!(f, ".{:0kept$}", value % unit, kept = kept)?;
+            }
+            for _ in kept..precision {
+                f.write_str("0")?;
+            }
+            return Ok(());
+        }
+
+        let int = magnitude / SCALE as u64;
+        let mut frac = magnitude % SCALE as u64;
+
+        if frac == 0 {
+            return write!(f, "{}{}", sign, int);
+        }
+        let mut width = DECIMALS as usize;
+        while frac.is_multiple_of(10) {
+            frac /= 10;
+            width -= 1;
+        }
+        write!(f, "{}{}.{:0width$}", sign, int, frac, width = width)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Option<i64> {
+        Amount::parse(s.as_bytes()).map(|amount| amount.0)
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("1"), Some(10000));
+        assert_eq!(parse("1.5"), Some(15000));
+        assert_eq!(parse("+1.5"), Some(15000));
+        assert_eq!(parse("-0.0001"), Some(-1));
+        assert_eq!(parse(".25"), Some(2500));
+        assert_eq!(parse("7."), Some(70000));
+        assert_eq!(parse("0.1234"), Some(1234));
+        assert_eq!(
+            parse("922337203685477.5807"),
+            Some(i64::MAX),
+            "largest representable amount"
+        );
+
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("."), None);
+        assert_eq!(parse("-"), None);
+        assert_eq!(parse("1e5"), None);
+        assert_eq!(parse("nan"), None);
+        assert_eq!(parse("inf"), None);
+        assert_eq!(parse("1.2.3"), None);
+        assert_eq!(parse(" 1"), None);
+        assert_eq!(parse("922337203685477.5808"), None);
+    }
+
+    #[test]
+    fn test_parse_rounding() {
+        // Below half.
+        assert_eq!(parse("0.00014"), Some(1));
+        // Above half.
+        assert_eq!(parse("0.00016"), Some(2));
+        assert_eq!(parse("0.000150001"), Some(2));
+        // Exactly half rounds to even.
+        assert_eq!(parse("0.00015"), Some(2));
+        assert_eq!(parse("0.00025"), Some(2));
+        assert_eq!(parse("-0.00025"), Some(-2));
+        assert_eq!(parse("0.99995"), Some(10000));
+    }
+
+    #[test]
+    fn test_parse_rounding_policies() {
+        let parse = |s: &str, rounding| Amount::parse_rounded(s.as_bytes(), rounding).map(|a| a.0);
+        use Rounding::*;
+
+        for (input, half_even, half_up, half_down, down) in [
+            ("0.00015", 2, 2, 1, 1),
+            ("0.00025", 2, 3, 2, 2),
+            ("0.000251", 3, 3, 3, 2),
+            ("0.00024", 2, 2, 2, 2),
+            ("-0.00025", -2, -3, -2, -2),
+            ("-0.00026", -3, -3, -3, -2),
+        ] {
+            assert_eq!(
+                parse(input, HalfEven),
+                Some(half_even),
+                "{} half-even",
+                input
+            );
+            assert_eq!(parse(input, HalfUp), Some(half_up), "{} half-up", input);
+            assert_eq!(
+                parse(input, HalfDown),
+                Some(half_down),
+                "{} half-down",
+                input
+            );
+            assert_eq!(parse(input, Down), Some(down), "{} down", input);
+        }
+
+        assert_eq!("half-even".parse(), Ok(HalfEven));
+        assert_eq!("down".parse(), Ok(Down));
+        assert_eq!(Rounding::default(), HalfEven);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Amount::from_raw(0).to_string(), "0");
+        assert_eq!(Amount::from_raw(10000).to_string(), "1");
+        assert_eq!(Amount::from_raw(15000).to_string(), "1.5");
+        assert_eq!(Amount::from_raw(-1).to_string(), "-0.0001");
+        assert_eq!(Amount::from_raw(1234567).to_string(), "123.4567");
+        assert_eq!(
+            Amount::from_raw(i64::MIN).to_string(),
+            "-922337203685477.5808"
+        );
+    }
+
+    #[test]
+    fn test_display_precision() {
+        assert_eq!(format!("{:.4}", Amount::from_raw(0)), "0.0000");
+        assert_eq!(format!("{:.4}", Amount::from_raw(15000)), "1.5000");
+        assert_eq!(format!("{:.4}", Amount::from_raw(-1)), "-0.0001");
+        assert_eq!(format!("{:.6}", Amount::from_raw(12345)), "1.234500");
+        assert_eq!(format!("{:.2}", Amount::from_raw(12350)), "1.24");
+        assert_eq!(format!("{:.2}", Amount::from_raw(12250)), "1.22");
+        assert_eq!(format!("{:.2}", Amount::from_raw(12251)), "1.23");
+        assert_eq!(format!("{:.0}", Amount::from_raw(25000)), "2");
+        assert_eq!(format!("{:.0}", Amount::from_raw(-35000)), "-4");
+        assert_eq!(format!("{:.2}", Amount::from_raw(-1)), "0.00");
+        assert_eq!(
+            format!("{:.4}", Amount::from_raw(i64::MAX)),
+            "922337203685477.5807"
+        );
+    }
+
+    #[test]
+    fn test_checked() {
+        let one = Amount::from_raw(SCALE);
+        assert_eq!(one.checked_add(one), Some(Amount::from_raw(2 * SCALE)));
+        assert_eq!(one.checked_sub(one), Some(Amount::from_raw(0)));
+        assert_eq!(Amount::from_raw(i64::MAX).checked_add(one), None);
+        assert_eq!(Amount::from_raw(i64::MIN).checked_sub(one), None);
+    }
+}
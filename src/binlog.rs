@@ -0,0 +1,491 @@
+use futures::{
+    stream::{self, TryStream, TryStreamExt},
+    AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt,
+};
+use std::{
+    convert::{TryFrom, TryInto},
+    error::Error,
+    io,
+};
+
+use crate::{
+    amount::Amount,
+    compression,
+    io::{ClientId, ParseError, Transaction, TransactionId},
+};
+
+/// Every binary log starts with these bytes followed by the [VERSION] of the format.
+pub const MAGIC: &[u8] = b"TXLOG";
+/// Version of the format written by [Encoder].
+///
+/// 1: Records are a varint length followed by the body described in [Encoder::encode].
+pub const VERSION: u8 = 1;
+
+/// Size of the reads of [decode].
+const READ_SIZE: usize = 64 * 1024;
+
+/// Longest audit a record can hold, in bytes.
+pub const MAX_AUDIT_LEN: usize = 64 * 1024;
+/// Longest body a record can have: the kind, the line and amount varints, the ids and the audit.
+const MAX_RECORD_LEN: usize = 1 + 10 + 2 + 4 + 10 + MAX_AUDIT_LEN;
+
+/// Flag of the kind byte, the record has an amount.
+const HAS_AMOUNT: u8 = 0x10;
+/// Flag of the kind byte, the record has an audit.
+const HAS_AUDIT: u8 = 0x20;
+
+/// Appends `value` to `buf` as a LEB128 varint.
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Reads a LEB128 varint from the start of `bytes`, returns it and its length.
+///
+/// Returns [None] if `bytes` ends before the varint does or it doesn't fit in a u64.
+fn get_varint(bytes: &[u8]) -> Option<(u64, usize)> {
+    let mut value = 0u64;
+    for (i, &byte) in bytes.iter().enumerate().take(10) {
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte < 0x80 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+/// Maps signed integers to unsigned ones so small magnitudes have short varints.
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+fn unzigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+#[derive(Debug, Default)]
+/// Encodes [Transactions](Transaction) in the binary log format.
+///
+/// Each record holds the line the transaction was found in, so rejections of a replayed log
+/// point at the lines of the original input.
+pub struct Encoder {
+    /// Line of the previous record, lines are stored as differences.
+    line: usize,
+}
+impl Encoder {
+    /// Appends the header of the format to `buf`.
+    pub fn header(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(MAGIC);
+        buf.push(VERSION);
+    }
+    /// Appends the record for `tx`, found in `line`, to `buf`.
+    ///
+    /// The body of a record is:
+    /// - The kind, a byte with the type in the low four bits and the [HAS_AMOUNT] and [HAS_AUDIT] flags.
+    /// - The difference with the line of the previous record, as a zigzag varint.
+    /// - The client as a little endian u16 and the tx as a little endian u32.
+    /// - The amount in ten-thousandths as a zigzag varint, if any.
+    /// - The audit as UTF-8 up to the end of the record, if any.
+    ///
+    /// Fails without writing anything if the audit is longer than [MAX_AUDIT_LEN].
+    pub fn encode(&mut self, line: usize, tx: &Transaction, buf: &mut Vec<u8>) -> io::Result<()> {
+        let (kind, client, id, amount, audit) = match tx {
+            Transaction::Deposit { client, tx, amount } => (0, client, tx, Some(amount), None),
+            Transaction::Withdrawal { client, tx, amount } => (1, client, tx, Some(amount), None),
+            Transaction::Dispute { client, tx, amount } => (2, client, tx, amount.as_ref(), None),
+            Transaction::Resolve { client, tx, amount } => (3, client, tx, amount.as_ref(), None),
+            Transaction::ChargeBack { client, tx, amount } => {
+                (4, client, tx, amount.as_ref(), None)
+            }
+            Transaction::Freeze { client, tx, audit } => (5, client, tx, None, audit.as_ref()),
+            Transaction::Unlock { client, tx, audit } => (6, client, tx, None, audit.as_ref()),
+        };
+        if let Some(audit) = audit.filter(|audit| audit.len() > MAX_AUDIT_LEN) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "The audit of tx {} in line {} is longer than {} bytes: {} bytes",
+                    id,
+                    line,
+                    MAX_AUDIT_LEN,
+                    audit.len()
+                ),
+            ));
+        }
+        let mut body = Vec::with_capacity(16);
+        let flags = amount.map_or(0, |_| HAS_AMOUNT) | audit.map_or(0, |_| HAS_AUDIT);
+        body.push(kind | flags);
+        put_varint(&mut body, zigzag(line as i64 - self.line as i64));
+        body.extend_from_slice(&client.0.to_le_bytes());
+        body.extend_from_slice(&id.0.to_le_bytes());
+        if let Some(amount) = amount {
+            put_varint(&mut body, zigzag(amount.raw()));
+        }
+        if let Some(audit) = audit {
+            body.extend_from_slice(audit.as_bytes());
+        }
+        put_varint(buf, body.len() as u64);
+        buf.extend_from_slice(&body);
+        self.line = line;
+        Ok(())
+    }
+}
+
+/// Writes every transaction in `transactions` to the binary log `file_out`,
+/// compressed according to its extension like the generators do.
+pub async fn write(
+    transactions: impl TryStream<Ok = (usize, Transaction), Error = ParseError>,
+    file_out: &str,
+) -> Result<(), Box<dyn Error>> {
+    let mut wri = compression::create(file_out).await?;
+    write_to(transactions, &mut wri).await?;
+    wri.close().await?;
+    Ok(())
+}
+
+/// Same as [write()] but to `wri`, which isn't closed.
+async fn write_to(
+    transactions: impl TryStream<Ok = (usize, Transaction), Error = ParseError>,
+    wri: &mut (impl AsyncWrite + Unpin),
+) -> Result<(), Box<dyn Error>> {
+    let transactions = transactions.into_stream();
+    futures::pin_mut!(transactions);
+    let mut encoder = Encoder::default();
+    let mut buf = Vec::with_capacity(READ_SIZE);
+    encoder.header(&mut buf);
+    while let Some((line, tx)) = transactions.try_next().await? {
+        encoder.encode(line, &tx, &mut buf)?;
+        if buf.len() >= READ_SIZE {
+            wri.write_all(&buf).await?;
+            buf.clear();
+        }
+    }
+    wri.write_all(&buf).await?;
+    Ok(())
+}
+
+/// Returns a stream of the [Transactions](Transaction) in the binary log `read`,
+/// each one paired with the line it was found in when the log was written.
+///
+/// The log is decoded as it's read, damaged logs and unknown versions end the stream with [ParseError::Corrupt].
+pub fn decode(
+    read: impl AsyncRead + Unpin,
+) -> impl TryStream<Ok = (usize, Transaction), Error = ParseError> {
+    let decoder = Decoder {
+        read,
+        buf: Vec::new(),
+        position: 0,
+        offset: 0,
+        line: 0,
+        header: false,
+        done: false,
+    };
+    Box::pin(stream::unfold(decoder, |mut decoder| async move {
+        if decoder.done {
+            return None;
+        }
+        let transaction = decoder.next().await.transpose()?;
+        // Errors are always fatal.
+        decoder.done = transaction.is_err();
+        Some((transaction, decoder))
+    }))
+}
+
+/// Decodes the records of a binary log as they are read.
+struct Decoder<R> {
+    read: R,
+    /// Bytes read but not decoded yet start at `position`.
+    buf: Vec<u8>,
+    position: usize,
+    /// Offset in the file of `buf[0]`.
+    offset: usize,
+    /// Line of the last record.
+    line: usize,
+    /// The header has been checked.
+    header: bool,
+    done: bool,
+}
+impl<R: AsyncRead + Unpin> Decoder<R> {
+    /// Returns the next transaction, or [None] at the end of the log.
+    async fn next(&mut self) -> Result<Option<(usize, Transaction)>, ParseError> {
+        if !self.header {
+            let len = MAGIC.len() + 1;
+            if !self.fill(len).await? || &self.buf[..MAGIC.len()] != MAGIC {
+                return Err(self.corrupt("not a binary log"));
+            }
+            let version = self.buf[MAGIC.len()];
+            if version != VERSION {
+                return Err(self.corrupt(format!("unsupported version {}", version)));
+            }
+            self.position = len;
+            self.header = true;
+        }
+
+        if !self.fill(1).await? {
+            return Ok(None);
+        }
+        // Length varints take at most 10 bytes.
+        let (len, start) = loop {
+            let rest = &self.buf[self.position..];
+            if let Some((len, start)) = get_varint(rest) {
+                break (len, start);
+            }
+            if rest.len() >= 10 || !self.fill(rest.len() + 1).await? {
+                return Err(self.corrupt("invalid record length"));
+            }
+        };
+        // Damaged lengths must not make the decoder allocate whatever they say.
+        let end = match usize::try_from(len) {
+            Ok(len) if len <= MAX_RECORD_LEN => start.checked_add(len),
+            _ => None,
+        };
+        let Some(end) = end else {
+            return Err(self.corrupt("invalid record length"));
+        };
+        if !self.fill(end).await? {
+            return Err(self.corrupt("truncated record"));
+        }
+        let body = &self.buf[self.position + start..self.position + end];
+        let (line, transaction) = self
+            .decode_body(body)
+            .ok_or_else(|| self.corrupt("invalid record"))?;
+        self.position += end;
+        self.line = line;
+        Ok(Some((line, transaction)))
+    }
+
+    /// Decodes the body of a record, [None] if it's invalid.
+    fn decode_body(&self, body: &[u8]) -> Option<(usize, Transaction)> {
+        let (&kind, rest) = body.split_first()?;
+        let (delta, len) = get_varint(rest)?;
+        let line = (self.line as i64)
+            .checked_add(unzigzag(delta))?
+            .try_into()
+            .ok()?;
+        let rest = &rest[len..];
+        let client = ClientId(u16::from_le_bytes(rest.get(..2)?.try_into().ok()?));
+        let tx = TransactionId(u32::from_le_bytes(rest.get(2..6)?.try_into().ok()?));
+        let mut rest = &rest[6..];
+        let amount = if kind & HAS_AMOUNT != 0 {
+            let (raw, len) = get_varint(rest)?;
+            rest = &rest[len..];
+            // Parsed inputs never have amounts that aren't positive, so neither do valid logs.
+            let amount = Amount::from_raw(unzigzag(raw));
+            if amount <= Amount::ZERO {
+                return None;
+            }
+            Some(amount)
+        } else {
+            None
+        };
+        let audit = if kind & HAS_AUDIT != 0 {
+            Some(std::str::from_utf8(rest).ok()?.to_string())
+        } else if rest.is_empty() {
+            None
+        } else {
+            return None;
+        };
+        let transaction = match (kind & 0x0f, amount, audit) {
+            (0, Some(amount), None) => Transaction::Deposit { client, tx, amount },
+            (1, Some(amount), None) => Transaction::Withdrawal { client, tx, amount },
+            (2, amount, None) => Transaction::Dispute { client, tx, amount },
+            (3, amount, None) => Transaction::Resolve { client, tx, amount },
+            (4, amount, None) => Transaction::ChargeBack { client, tx, amount },
+            (5, None, audit) => Transaction::Freeze { client, tx, audit },
+            (6, None, audit) => Transaction::Unlock { client, tx, audit },
+            _ => return None,
+        };
+        Some((line, transaction))
+    }
+
+    /// Reads until there are at least `len` bytes after `position`,
+    /// returns false if the log ends before that.
+    async fn fill(&mut self, len: usize) -> Result<bool, ParseError> {
+        while self.buf.len() - self.position < len {
+            // Drop the decoded bytes before reading more.
+            if self.position > 0 {
+                self.buf.drain(..self.position);
+                self.offset += self.position;
+                self.position = 0;
+            }
+            let filled = self.buf.len();
+            self.buf.resize(filled + READ_SIZE.max(len), 0);
+            let read = self.read.read(&mut self.buf[filled..]).await?;
+            self.buf.truncate(filled + read);
+            if read == 0 {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn corrupt(&self, text: impl Into<String>) -> ParseError {
+        ParseError::Corrupt {
+            line: self.line,
+            column: self.offset + self.position,
+            text: text.into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::stream;
+
+    fn transactions() -> Vec<(usize, Transaction)> {
+        let amount = |s: &str| s.parse::<Amount>().unwrap();
+        vec![
+            (
+                2,
+                Transaction::Deposit {
+                    client: ClientId(1),
+                    tx: TransactionId(1),
+                    amount: amount("1.5"),
+                },
+            ),
+            (
+                3,
+                Transaction::Withdrawal {
+                    client: ClientId(u16::MAX),
+                    tx: TransactionId(u32::MAX),
+                    amount: amount("922337203685477.5807"),
+                },
+            ),
+            (
+                7,
+                Transaction::Dispute {
+                    client: ClientId(1),
+                    tx: TransactionId(1),
+                    amount: None,
+                },
+            ),
+            (
+                5,
+                Transaction::ChargeBack {
+                    client: ClientId(1),
+                    tx: TransactionId(1),
+                    amount: Some(amount("0.0001")),
+                },
+            ),
+            (
+                1000000,
+                Transaction::Freeze {
+                    client: ClientId(2),
+                    tx: TransactionId(9),
+                    audit: Some("bob: \"fraud\", see #42".to_string()),
+                },
+            ),
+            (
+                1000001,
+                Transaction::Unlock {
+                    client: ClientId(2),
+                    tx: TransactionId(10),
+                    audit: None,
+                },
+            ),
+        ]
+    }
+
+    async fn encode(transactions: Vec<(usize, Transaction)>) -> Vec<u8> {
+        let mut wri = Vec::new();
+        write_to(stream::iter(transactions.into_iter().map(Ok)), &mut wri)
+            .await
+            .unwrap();
+        wri
+    }
+
+    #[smol_potat::test]
+    async fn test_round_trip() {
+        let log = encode(transactions()).await;
+        assert!(log.starts_with(b"TXLOG\x01"));
+        let decoded: Vec<_> = decode(&log[..]).try_collect().await.unwrap();
+        assert_eq!(decoded, transactions());
+
+        // A deposit in line 2 of client 1 with tx 1 and 1.5 takes 12 bytes.
+        assert_eq!(
+            &log[6..18],
+            b"\x0b\x10\x04\x01\x00\x01\x00\x00\x00\xb0\xea\x01"
+        );
+
+        // The longest audit fits, anything longer is refused.
+        let freeze = |audit: String| {
+            (
+                1,
+                Transaction::Freeze {
+                    client: ClientId(1),
+                    tx: TransactionId(1),
+                    audit: Some(audit),
+                },
+            )
+        };
+        let longest = vec![freeze("a".repeat(MAX_AUDIT_LEN))];
+        let log = encode(longest.clone()).await;
+        let decoded: Vec<_> = decode(&log[..]).try_collect().await.unwrap();
+        assert_eq!(decoded, longest);
+        let mut buf = Vec::new();
+        let (line, tx) = freeze("a".repeat(MAX_AUDIT_LEN + 1));
+        assert!(Encoder::default().encode(line, &tx, &mut buf).is_err());
+        assert!(buf.is_empty());
+
+        let empty = encode(Vec::new()).await;
+        let decoded: Vec<_> = decode(&empty[..]).try_collect().await.unwrap();
+        assert!(decoded.is_empty());
+    }
+
+    #[smol_potat::test]
+    async fn test_corrupt() {
+        async fn error(log: &[u8]) -> ParseError {
+            decode(log).try_collect::<Vec<_>>().await.unwrap_err()
+        }
+        let log = encode(transactions()).await;
+
+        let err = error(b"type,client,tx,amount\n").await;
+        assert!(
+            matches!(err, ParseError::Corrupt { line: 0, column: 0, ref text } if text == "not a binary log")
+        );
+        let err = error(b"TXLOG\x02").await;
+        assert!(
+            matches!(err, ParseError::Corrupt { ref text, .. } if text == "unsupported version 2")
+        );
+        // Cut in the middle of the second record.
+        let err = error(&log[..20]).await;
+        assert!(
+            matches!(err, ParseError::Corrupt { line: 2, column: 18, ref text } if text == "truncated record")
+        );
+        // Lengths over the longest record.
+        let err = error(b"TXLOG\x01\xff\xff\xff\xff\xff\xff\xff\xff\x7f").await;
+        assert!(
+            matches!(err, ParseError::Corrupt { line: 0, column: 6, ref text } if text == "invalid record length")
+        );
+        let mut long = b"TXLOG\x01".to_vec();
+        put_varint(&mut long, MAX_RECORD_LEN as u64 + 1);
+        let err = error(&long).await;
+        assert!(
+            matches!(err, ParseError::Corrupt { ref text, .. } if text == "invalid record length")
+        );
+        // Amounts that aren't positive.
+        for raw in [-10_000, 0] {
+            let deposit = Transaction::Deposit {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: Amount::from_raw(raw),
+            };
+            let err = error(&encode(vec![(1, deposit)]).await).await;
+            assert!(
+                matches!(err, ParseError::Corrupt { line: 0, column: 6, ref text } if text == "invalid record"),
+                "{}",
+                raw
+            );
+        }
+        // Unknown type.
+        let mut damaged = log.clone();
+        damaged[7] = 0x1f;
+        let err = error(&damaged).await;
+        assert!(
+            matches!(err, ParseError::Corrupt { line: 0, column: 6, ref text } if text == "invalid record")
+        );
+    }
+}
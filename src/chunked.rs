@@ -0,0 +1,371 @@
+use futures::{future, stream, StreamExt, TryStream};
+use memmap2::Mmap;
+use smol::{
+    io::{AsyncRead, AsyncReadExt, Empty},
+    spawn, unblock, Task,
+};
+use std::{collections::VecDeque, io::Error, ops::Deref, ops::Range, sync::Arc, vec};
+
+use crate::io::{
+    parse_record, ErrorFilter, Layout, ParseError, ParseOptions, RecordScanner, Transaction,
+};
+
+// Tuned with time_multi.sh
+/// Minimum size of the blocks of records handed to the parsing workers.
+const BLOCK_SIZE: usize = 1024 * 1024;
+
+type Parsed = Vec<Result<(usize, Transaction), ParseError>>;
+
+/// Same as [parse](crate::io::parse) but parses the input in blocks of whole records on up to `workers` threads.
+///
+/// The results are returned in the same order as in the input.
+pub fn parse_chunked(
+    read: impl AsyncRead + Unpin,
+    options: ParseOptions,
+    workers: usize,
+) -> impl TryStream<Ok = (usize, Transaction), Error = ParseError> {
+    let input = Input::Read {
+        read,
+        partial: Vec::new(),
+    };
+    chunks(input, options, workers)
+}
+
+/// Same as [parse_chunked] but the blocks are slices of `map` instead of copies of the input.
+pub fn parse_mapped(
+    map: Mmap,
+    options: ParseOptions,
+    workers: usize,
+) -> impl TryStream<Ok = (usize, Transaction), Error = ParseError> {
+    let input: Input<Empty> = Input::Mapped {
+        map: Arc::new(map),
+        start: 0,
+    };
+    chunks(input, options, workers)
+}
+
+fn chunks<R: AsyncRead + Unpin>(
+    input: Input<R>,
+    options: ParseOptions,
+    workers: usize,
+) -> impl TryStream<Ok = (usize, Transaction), Error = ParseError> {
+    let mut errors = ErrorFilter::new(options.on_error);
+    let chunks = Chunks {
+        input,
+        options,
+        workers: workers.max(1),
+        scanner: RecordScanner::default(),
+        line: 1,
+        layout: None,
+        eof: false,
+        in_flight: VecDeque::new(),
+        parsed: Vec::new().into_iter(),
+    };
+    Box::pin(
+        stream::unfold(chunks, |mut chunks| async move {
+            let transaction = chunks.next().await?;
+            Some((transaction, chunks))
+        })
+        .filter_map(move |transaction| future::ready(errors.filter(transaction))),
+    )
+}
+
+/// Where [Chunks] gets its blocks from.
+enum Input<R> {
+    Read {
+        read: R,
+        /// Input that has been read but not handed to a worker, it starts with a record.
+        partial: Vec<u8>,
+    },
+    Mapped {
+        map: Arc<Mmap>,
+        /// Start of the input that hasn't been handed to a worker.
+        start: usize,
+    },
+}
+
+/// A block of whole records.
+enum Block {
+    Read(Vec<u8>),
+    Mapped(Arc<Mmap>, Range<usize>),
+}
+impl Deref for Block {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        match self {
+            Block::Read(bytes) => bytes,
+            Block::Mapped(map, range) => &map[range.clone()],
+        }
+    }
+}
+
+/// Splits the input in blocks of whole records and parses them in parallel.
+struct Chunks<R> {
+    input: Input<R>,
+    options: ParseOptions,
+    workers: usize,
+    /// Has scanned the input that has been read but not handed to a worker,
+    /// which doesn't contain the end of a record.
+    scanner: RecordScanner,
+    /// Line where the input that hasn't been handed to a worker starts.
+    line: usize,
+    /// Read from the first record.
+    layout: Option<Arc<Layout>>,
+    eof: bool,
+    /// Blocks being parsed, in the order of the input.
+    in_flight: VecDeque<Task<Parsed>>,
+    /// Results of the oldest block.
+    parsed: vec::IntoIter<Result<(usize, Transaction), ParseError>>,
+}
+impl<R: AsyncRead + Unpin> Chunks<R> {
+    /// Returns the next parsing result in the order of the input.
+    async fn next(&mut self) -> Option<Result<(usize, Transaction), ParseError>> {
+        loop {
+            if let Some(transaction) = self.parsed.next() {
+                return Some(transaction);
+            }
+            // Keep every worker busy.
+            while !self.eof && self.in_flight.len() < self.workers {
+                if let Err(err) = self.dispatch().await {
+                    self.eof = true;
+                    self.in_flight.clear();
+                    return Some(Err(err));
+                }
+            }
+            let parsed = self.in_flight.pop_front()?.await;
+            self.parsed = parsed.into_iter();
+        }
+    }
+
+    /// Reads the next block of whole records and hands it to a worker.
+    ///
+    /// Only returns fatal errors, the others are handed over with the results of the block.
+    async fn dispatch(&mut self) -> Result<(), ParseError> {
+        let Some((line, block)) = self.next_block().await? else {
+            self.eof = true;
+            return Ok(());
+        };
+        let mut start = 0;
+        let mut line = line;
+        // Like parse, a first record that can't be read is skipped and the next one is the first.
+        let mut skipped = Vec::new();
+        let mut scanner = RecordScanner::default();
+        while self.layout.is_none() && start < block.len() {
+            let end = scanner
+                .find_end(&block[start..])
+                .map_or(block.len(), |end| start + end);
+            let lines = scanner.finish();
+            match Layout::from_header(&block[start..end], line, &self.options.aliases) {
+                Ok(Some(header)) => self.layout = Some(Arc::new(header)),
+                Ok(None) => {
+                    self.layout = Some(Arc::new(Layout::positional()));
+                    break;
+                }
+                Err(err) if err.is_fatal() => return Err(err),
+                Err(err) => skipped.push(Err(err)),
+            }
+            start = (end + 1).min(block.len());
+            line += lines;
+        }
+        let layout = self.layout.clone();
+        let options = self.options.clone();
+        self.in_flight.push_back(spawn(unblock(move || {
+            let mut parsed = skipped;
+            if let Some(layout) = layout {
+                parsed.extend(parse_block(&block[start..], line, &layout, &options));
+            }
+            parsed
+        })));
+        Ok(())
+    }
+
+    /// Returns the next block of whole records and the line it starts in,
+    /// or [None] at the end of the input.
+    async fn next_block(&mut self) -> Result<Option<(usize, Block)>, Error> {
+        // End of the last whole record and the lines up to it.
+        let mut cut = None;
+        let mut lines = 0;
+        let block = match &mut self.input {
+            Input::Read { read, partial } => {
+                let mut scanned = partial.len();
+                loop {
+                    let len = partial.len();
+                    if len >= BLOCK_SIZE && cut.is_some() {
+                        break;
+                    }
+                    partial.resize(len + BLOCK_SIZE, 0);
+                    let read = read.read(&mut partial[len..]).await?;
+                    partial.truncate(len + read);
+                    if read == 0 {
+                        // The last record doesn't need to end in a line break.
+                        if !partial.is_empty() {
+                            cut = Some(partial.len());
+                            lines += self.scanner.finish();
+                        }
+                        break;
+                    }
+                    while let Some(end) = self.scanner.find_end(&partial[scanned..]) {
+                        scanned += end + 1;
+                        cut = Some(scanned);
+                        lines += self.scanner.finish();
+                    }
+                    scanned = partial.len();
+                }
+                let Some(cut) = cut else {
+                    return Ok(None);
+                };
+                let rest = partial.split_off(cut);
+                Block::Read(std::mem::replace(partial, rest))
+            }
+            Input::Mapped { map, start } => {
+                let rest = &map[*start..];
+                let mut end = 0;
+                while end < rest.len() && end < BLOCK_SIZE {
+                    match self.scanner.find_end(&rest[end..]) {
+                        Some(record) => end += record + 1,
+                        // The last record doesn't need to end in a line break.
+                        None => end = rest.len(),
+                    }
+                    lines += self.scanner.finish();
+                }
+                if end == 0 {
+                    return Ok(None);
+                }
+                let block = Block::Mapped(map.clone(), *start..*start + end);
+                *start += end;
+                block
+            }
+        };
+        let line = self.line;
+        self.line += lines;
+        Ok(Some((line, block)))
+    }
+}
+
+/// Parses every record in `block`, which holds whole records starting in line `line` and follows `layout`.
+pub fn parse_block(
+    block: &[u8],
+    mut line: usize,
+    layout: &Layout,
+    options: &ParseOptions,
+) -> Parsed {
+    let mut parsed = Vec::new();
+    let mut scanner = RecordScanner::default();
+    let mut rest = block;
+    while !rest.is_empty() {
+        let end = scanner.find_end(rest).unwrap_or(rest.len());
+        parsed.push(parse_record(&rest[..end], line, layout, options).map(|tx| (line, tx)));
+        line += scanner.finish();
+        rest = &rest[(end + 1).min(rest.len())..];
+    }
+    parsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::{parse, ErrorPolicy};
+    use futures::{io::BufReader, TryStreamExt};
+
+    #[smol_potat::test]
+    async fn test_same_as_parse() {
+        let mut input = String::from("client, type, tx, amount, audit\n");
+        for i in 0..200_000 {
+            input.push_str(&match i % 5 {
+                0 => format!("{}, deposit, {}, {}.5\n", i % 100, i, i % 7 + 1),
+                1 => format!("{}, withdrawal, {}, 0.25\r\n", i % 100, i),
+                2 => format!("{}, dispute, {},\n", i % 100, i - 2),
+                3 => format!("{}, freeze, {}, , \"multi\nline, {}\"\n", i % 100, i, i),
+                _ => format!("{}, lol, {}\n", i % 100, i),
+            });
+        }
+        input.push_str("1, deposit, 1, 1");
+        let options = ParseOptions {
+            on_error: ErrorPolicy::SkipSilently,
+            ..Default::default()
+        };
+
+        let expected: Vec<_> = parse(BufReader::new(input.as_bytes()), options.clone())
+            .try_collect()
+            .await
+            .unwrap();
+        for workers in [1, 4].iter() {
+            let chunked: Vec<_> = parse_chunked(input.as_bytes(), options.clone(), *workers)
+                .try_collect()
+                .await
+                .unwrap();
+            assert_eq!(chunked, expected, "{} workers", workers);
+        }
+
+        let path = std::env::temp_dir().join(format!("chunked-{}.csv", std::process::id()));
+        std::fs::write(&path, &input).unwrap();
+        let map = unsafe { Mmap::map(&std::fs::File::open(&path).unwrap()).unwrap() };
+        std::fs::remove_file(&path).unwrap();
+        let mapped: Vec<_> = parse_mapped(map, options, 4).try_collect().await.unwrap();
+        assert_eq!(mapped, expected);
+    }
+
+    #[smol_potat::test]
+    async fn test_errors() {
+        let input = "type, client, tx, amount\ndeposit, 1, 1, 1\nlol, 1, 2, 1\n";
+        let err = parse_chunked(input.as_bytes(), ParseOptions::default(), 2)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ParseError::Type { line: 3, .. }));
+
+        let input = "type, amount\ndeposit, 1\n";
+        let err = parse_chunked(input.as_bytes(), ParseOptions::default(), 2)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ParseError::MissingColumn { line: 1, .. }));
+
+        // A first record without any known column is a transaction, not a header.
+        let input = "lol, 2, 5, 3.0\ndeposit, 1, 1, 1\n";
+        let err = parse_chunked(input.as_bytes(), ParseOptions::default(), 2)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ParseError::Type { line: 1, .. }));
+
+        // A first record that can't be read isn't fatal, the one after it is the first instead.
+        for input in [
+            "\"type\"x,client,tx,amount\ndeposit,1,1,5\ndeposit,1,2,5\n",
+            "\"type\"x,client,tx,amount\nclient,type,tx,amount\n1,deposit,1,5\n",
+        ] {
+            let err = parse_chunked(input.as_bytes(), ParseOptions::default(), 2)
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap_err();
+            assert!(matches!(err, ParseError::Syntax { line: 1, .. }));
+
+            let options = ParseOptions {
+                on_error: ErrorPolicy::SkipSilently,
+                ..Default::default()
+            };
+            let expected: Vec<_> = parse(BufReader::new(input.as_bytes()), options.clone())
+                .try_collect()
+                .await
+                .unwrap();
+            assert!(!expected.is_empty());
+            let chunked: Vec<_> = parse_chunked(input.as_bytes(), options, 2)
+                .try_collect()
+                .await
+                .unwrap();
+            assert_eq!(chunked, expected, "{}", input);
+        }
+
+        // Headerless and empty inputs.
+        let parsed: Vec<_> = parse_chunked(&b"deposit, 1, 1, 1"[..], ParseOptions::default(), 2)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(parsed.len(), 1);
+        let parsed: Vec<_> = parse_chunked(&b""[..], ParseOptions::default(), 2)
+            .try_collect()
+            .await
+            .unwrap();
+        assert!(parsed.is_empty());
+    }
+}
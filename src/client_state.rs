@@ -1,47 +1,105 @@
+use parse_display::{Display, FromStr};
 use rustc_hash::FxHashMap;
+use serde::Serialize;
 
-use crate::io::{ClientId, Output, Transaction, TransactionId};
+use crate::{
+    amount::Amount,
+    events::*,
+    io::{ClientId, Output, Transaction, TransactionId},
+};
+
+#[derive(Display, FromStr, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[display(style = "kebab-case")]
+/// Decides which kinds of transactions can be disputed.
+pub enum DisputePolicy {
+    /// Only deposits can be disputed, withdrawals are not recorded.
+    #[default]
+    Deposits,
+    /// Both deposits and withdrawals can be disputed.
+    DepositsAndWithdrawals,
+}
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
-/// Represents the different states that a deposit can be in.
-enum DepositStateType {
+/// Represents the kinds of transactions that can be disputed.
+enum TransactionKind {
+    Deposit,
+    Withdrawal,
+}
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+/// Represents the different states that a disputable transaction can be in.
+enum TransactionStateType {
     Normal,
     Disputed,
     ChargedBack,
 }
 #[derive(PartialEq, Debug, Clone, Copy)]
-/// Aggregates the information of a single deposit.
-struct DepositState {
-    ty: DepositStateType,
-    amount: f64,
+/// Aggregates the information of a single disputable transaction.
+///
+/// A transaction can be disputed in several parts, `disputed` is the part that is currently disputed
+/// and it never exceeds `amount`.
+struct TransactionState {
+    kind: TransactionKind,
+    ty: TransactionStateType,
+    amount: Amount,
+    disputed: Amount,
 }
-impl DepositState {
-    /// Returns a new DepositState with [DepositStateType::Normal]
-    /// and the amount provided.
-    fn new(amount: f64) -> Self {
+impl TransactionState {
+    /// Returns a new TransactionState with [TransactionStateType::Normal]
+    /// and the kind and amount provided.
+    fn new(kind: TransactionKind, amount: Amount) -> Self {
         Self {
-            ty: DepositStateType::Normal,
+            kind,
+            ty: TransactionStateType::Normal,
             amount,
+            disputed: Amount::ZERO,
         }
     }
+    /// Returns the part of the transaction that can still be disputed.
+    fn undisputed(&self) -> Amount {
+        self.amount
+            .checked_sub(self.disputed)
+            .unwrap_or(Amount::ZERO)
+    }
 }
-#[derive(PartialEq, Debug, Clone)]
+#[derive(Serialize, Display, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[display(style = "lowercase")]
+/// Why an account is locked.
+pub enum LockReason {
+    /// A transaction was charged back.
+    ChargeBack,
+    /// Support froze the account.
+    Freeze,
+}
+
+#[derive(PartialEq, Debug, Clone, Default)]
+/// Summarizes the activity of a single client, only rejected transactions are left out.
+pub struct ClientStats {
+    /// Number of deposits.
+    pub deposits: u32,
+    /// Number of transactions currently disputed, partially or fully.
+    pub open_disputes: u32,
+    /// Number of chargebacks.
+    pub chargebacks: u32,
+    /// Sum of the deposits, saturates instead of overflowing.
+    pub deposited: Amount,
+    /// Sum of the withdrawals, saturates instead of overflowing.
+    pub withdrawn: Amount,
+    /// Id of the last transaction applied.
+    /// Disputes, resolves and chargebacks use the id of the transaction they refer to.
+    pub last_tx: Option<TransactionId>,
+    /// Why the account is locked, [None] if it isn't.
+    pub lock_reason: Option<LockReason>,
+}
+
+#[derive(PartialEq, Debug, Clone, Default)]
 /// Aggregates the information of a single client.
 pub struct ClientState {
-    deposits: FxHashMap<TransactionId, DepositState>,
-    available: f64,
-    held: f64,
+    transactions: FxHashMap<TransactionId, TransactionState>,
+    available: Amount,
+    held: Amount,
     locked: bool,
-}
-impl Default for ClientState {
-    fn default() -> Self {
-        Self {
-            deposits: Default::default(),
-            available: 0.0,
-            held: 0.0,
-            locked: false,
-        }
-    }
+    stats: ClientStats,
 }
 
 impl ClientState {
@@ -49,131 +107,252 @@ impl ClientState {
     ///
     /// Refer to the assignment doc for the precise semantics of this function.
     /// I would put a link here but I don't have one.
-    pub fn process_transaction(&mut self, tx: Transaction) {
-        if self.locked {
+    ///
+    /// When `policy` allows disputing withdrawals, disputing one holds its amount as a provisional
+    /// credit, resolving it releases the hold and charging it back returns the amount to the available funds.
+    pub fn process_transaction(
+        &mut self,
+        tx: Transaction,
+        policy: DisputePolicy,
+        events: &mut impl LedgerEvents,
+    ) {
+        // Only an administrative unlock can change a locked account.
+        if self.locked && !matches!(tx, Transaction::Unlock { .. }) {
+            events.record(LedgerEvent::AccountLocked(AccountLocked {
+                client: tx.client(),
+                tx: tx.id(),
+            }));
             return;
         }
 
         use Transaction::*;
+        use TransactionKind as Kind;
         // I have consciously made the choice to leave the logic all in the same match statement for conciseness and maintainability.
         // If the match arms got out of hand or contained a lot of complex logic, they should be moved to their own functions.
         match tx {
-            Deposit { tx, amount, .. } => {
-                self.deposits.insert(tx, DepositState::new(amount));
-                self.available += amount
+            Deposit { client, tx, amount } => {
+                let Some((available, held)) =
+                    checked_balances(self.available.checked_add(amount), Some(self.held))
+                else {
+                    events.record(LedgerEvent::Overflow(Overflow { client, tx }));
+                    return;
+                };
+                self.available = available;
+                self.held = held;
+                self.transactions
+                    .insert(tx, TransactionState::new(Kind::Deposit, amount));
+                self.stats.deposits += 1;
+                self.stats.deposited = self.stats.deposited.saturating_add(amount);
+                self.stats.last_tx = Some(tx);
+                events.record(LedgerEvent::Deposited(Deposited { client, tx, amount }));
             }
-            Withdrawal { client, amount, .. } => {
-                if self.available - amount < 0.0 {
-                    handle_insufficient_funds(client, amount, self.available);
+            Withdrawal { client, tx, amount } => {
+                if self.available < amount {
+                    events.record(LedgerEvent::InsufficientFunds(InsufficientFunds {
+                        client,
+                        tx,
+                        amount,
+                        available: self.available,
+                    }));
+                    return;
+                }
+                let Some((available, held)) =
+                    checked_balances(self.available.checked_sub(amount), Some(self.held))
+                else {
+                    events.record(LedgerEvent::Overflow(Overflow { client, tx }));
                     return;
+                };
+                self.available = available;
+                self.held = held;
+                if policy == DisputePolicy::DepositsAndWithdrawals {
+                    self.transactions
+                        .insert(tx, TransactionState::new(Kind::Withdrawal, amount));
                 }
-                self.available -= amount
+                self.stats.withdrawn = self.stats.withdrawn.saturating_add(amount);
+                self.stats.last_tx = Some(tx);
+                events.record(LedgerEvent::Withdrawn(Withdrawn { client, tx, amount }));
             }
-            Dispute { client, tx } => {
-                if let Some(deposit) = self.deposits.get_mut(&tx) {
-                    if deposit.ty != DepositStateType::Normal {
-                        handle_already_disputed_deposit(client, tx);
+            Dispute { client, tx, amount } => {
+                if let Some(transaction) = self.transactions.get_mut(&tx) {
+                    let undisputed = transaction.undisputed();
+                    if transaction.ty == TransactionStateType::ChargedBack
+                        || undisputed == Amount::ZERO
+                    {
+                        events.record(LedgerEvent::AlreadyDisputed(AlreadyDisputed { client, tx }));
                         return;
                     }
-                    deposit.ty = DepositStateType::Disputed;
-                    self.available -= deposit.amount;
-                    self.held += deposit.amount;
+                    let amount = amount.unwrap_or(undisputed);
+                    if amount <= Amount::ZERO || amount > undisputed {
+                        events.record(LedgerEvent::InvalidDisputeAmount(InvalidDisputeAmount {
+                            client,
+                            tx,
+                            amount,
+                        }));
+                        return;
+                    }
+                    let (Some((available, held)), Some(disputed)) = (
+                        checked_balances(
+                            match transaction.kind {
+                                Kind::Deposit => self.available.checked_sub(amount),
+                                Kind::Withdrawal => Some(self.available),
+                            },
+                            self.held.checked_add(amount),
+                        ),
+                        transaction.disputed.checked_add(amount),
+                    ) else {
+                        events.record(LedgerEvent::Overflow(Overflow { client, tx }));
+                        return;
+                    };
+                    self.available = available;
+                    self.held = held;
+                    if transaction.ty == TransactionStateType::Normal {
+                        self.stats.open_disputes += 1;
+                    }
+                    transaction.ty = TransactionStateType::Disputed;
+                    transaction.disputed = disputed;
+                    self.stats.last_tx = Some(tx);
+                    events.record(LedgerEvent::Disputed(Disputed { client, tx, amount }));
                 } else {
-                    handle_non_existent_deposit(client, tx);
+                    events.record(LedgerEvent::NonExistentTransaction(
+                        NonExistentTransaction { client, tx },
+                    ));
                 }
             }
-            Resolve { client, tx } => {
-                if let Some(deposit) = self.deposits.get_mut(&tx) {
-                    if deposit.ty != DepositStateType::Disputed {
-                        handle_not_disputed_deposit(client, tx);
+            Resolve { client, tx, amount } => {
+                if let Some(transaction) = self.transactions.get_mut(&tx) {
+                    if transaction.ty != TransactionStateType::Disputed {
+                        events.record(LedgerEvent::NotDisputed(NotDisputed { client, tx }));
+                        return;
+                    }
+                    let amount = amount.unwrap_or(transaction.disputed);
+                    if amount <= Amount::ZERO || amount > transaction.disputed {
+                        events.record(LedgerEvent::InvalidDisputeAmount(InvalidDisputeAmount {
+                            client,
+                            tx,
+                            amount,
+                        }));
                         return;
                     }
-                    deposit.ty = DepositStateType::Normal;
-                    self.available += deposit.amount;
-                    self.held -= deposit.amount;
+                    let (Some((available, held)), Some(disputed)) = (
+                        checked_balances(
+                            match transaction.kind {
+                                Kind::Deposit => self.available.checked_add(amount),
+                                Kind::Withdrawal => Some(self.available),
+                            },
+                            self.held.checked_sub(amount),
+                        ),
+                        transaction.disputed.checked_sub(amount),
+                    ) else {
+                        events.record(LedgerEvent::Overflow(Overflow { client, tx }));
+                        return;
+                    };
+                    self.available = available;
+                    self.held = held;
+                    transaction.disputed = disputed;
+                    if disputed == Amount::ZERO {
+                        transaction.ty = TransactionStateType::Normal;
+                        self.stats.open_disputes -= 1;
+                    }
+                    self.stats.last_tx = Some(tx);
+                    events.record(LedgerEvent::Resolved(Resolved { client, tx, amount }));
                 } else {
-                    handle_non_existent_deposit(client, tx);
+                    events.record(LedgerEvent::NonExistentTransaction(
+                        NonExistentTransaction { client, tx },
+                    ));
                 }
             }
-            ChargeBack { client, tx } => {
-                if let Some(deposit) = self.deposits.get_mut(&tx) {
-                    if deposit.ty != DepositStateType::Disputed {
-                        handle_not_disputed_deposit(client, tx);
+            ChargeBack { client, tx, amount } => {
+                if let Some(transaction) = self.transactions.get_mut(&tx) {
+                    if transaction.ty != TransactionStateType::Disputed {
+                        events.record(LedgerEvent::NotDisputed(NotDisputed { client, tx }));
+                        return;
+                    }
+                    let disputed = transaction.disputed;
+                    let amount = amount.unwrap_or(disputed);
+                    if amount <= Amount::ZERO || amount > disputed {
+                        events.record(LedgerEvent::InvalidDisputeAmount(InvalidDisputeAmount {
+                            client,
+                            tx,
+                            amount,
+                        }));
                         return;
                     }
-                    deposit.ty = DepositStateType::ChargedBack;
-                    self.held -= deposit.amount;
+                    // A chargeback settles the dispute, whatever is not charged back is released.
+                    let released = disputed.checked_sub(amount);
+                    let Some((available, held)) = checked_balances(
+                        match transaction.kind {
+                            Kind::Deposit => match released {
+                                Some(released) => self.available.checked_add(released),
+                                None => None,
+                            },
+                            Kind::Withdrawal => self.available.checked_add(amount),
+                        },
+                        self.held.checked_sub(disputed),
+                    ) else {
+                        events.record(LedgerEvent::Overflow(Overflow { client, tx }));
+                        return;
+                    };
+                    self.available = available;
+                    self.held = held;
+                    transaction.ty = TransactionStateType::ChargedBack;
+                    transaction.disputed = Amount::ZERO;
                     self.locked = true;
-                    handle_account_locked(client, tx);
+                    self.stats.open_disputes -= 1;
+                    self.stats.chargebacks += 1;
+                    self.stats.last_tx = Some(tx);
+                    self.stats.lock_reason = Some(LockReason::ChargeBack);
+                    events.record(LedgerEvent::ChargedBack(ChargedBack { client, tx, amount }));
                 } else {
-                    handle_non_existent_deposit(client, tx);
+                    events.record(LedgerEvent::NonExistentTransaction(
+                        NonExistentTransaction { client, tx },
+                    ));
+                }
+            }
+            Freeze { client, tx, audit } => {
+                self.locked = true;
+                self.stats.last_tx = Some(tx);
+                self.stats.lock_reason = Some(LockReason::Freeze);
+                events.record(LedgerEvent::Frozen(Frozen { client, tx, audit }));
+            }
+            Unlock { client, tx, audit } => {
+                if !self.locked {
+                    events.record(LedgerEvent::NotLocked(NotLocked { client, tx }));
+                    return;
                 }
+                self.locked = false;
+                self.stats.last_tx = Some(tx);
+                self.stats.lock_reason = None;
+                events.record(LedgerEvent::Unlocked(Unlocked { client, tx, audit }));
             }
         }
     }
 }
 
-// In a production system, these functions would submit anonymized structured logs and probably a notification to some security system.
-#[allow(unused_variables)]
-fn handle_insufficient_funds(client: ClientId, amount: f64, available: f64) {
-    #[cfg(feature = "stderr")]
-    eprintln!(
-        "Client: {} attempted to withdraw {} while only {} were available in his account.",
-        client, amount, available
-    );
-}
-#[allow(unused_variables)]
-fn handle_already_disputed_deposit(client: ClientId, tx: TransactionId) {
-    #[cfg(feature = "stderr")]
-    eprintln!(
-        "Client: {} attempted to dispute transaction {} which had already been disputed.",
-        client, tx
-    );
-}
-#[allow(unused_variables)]
-fn handle_not_disputed_deposit(client: ClientId, tx: TransactionId) {
-    #[cfg(feature = "stderr")]
-    eprintln!(
-        "Client: {} attempted to resolve or charge back transaction {} which is not disputed.",
-        client, tx
-    );
-}
-#[allow(unused_variables)]
-fn handle_non_existent_deposit(client: ClientId, tx: TransactionId) {
-    #[cfg(feature = "stderr")]
-    eprintln!(
-        "Client: {} attempted to dispute a non existent deposit {}.",
-        client, tx
-    );
-}
-// In a production system this would probably send a notification to other services which would
-// contact the user and the customer support team.
-#[allow(unused_variables)]
-fn handle_account_locked(client: ClientId, tx: TransactionId) {
-    #[cfg(feature = "stderr")]
-    eprintln!(
-        "Client: {} is locked after issuing a chargeback for deposit: {}.",
-        client, tx
-    );
+/// Returns the new `available` and `held` balances if both of them and their total are representable.
+fn checked_balances(available: Option<Amount>, held: Option<Amount>) -> Option<(Amount, Amount)> {
+    let (available, held) = (available?, held?);
+    available.checked_add(held)?;
+    Some((available, held))
 }
 
-impl Into<Output> for (ClientId, ClientState) {
-    fn into(self) -> Output {
-        let (
-            client,
-            ClientState {
-                available,
-                held,
-                locked,
-                ..
-            },
-        ) = self;
+impl From<(ClientId, ClientState)> for Output {
+    fn from((client, state): (ClientId, ClientState)) -> Self {
+        let ClientState {
+            available,
+            held,
+            locked,
+            stats,
+            ..
+        } = state;
         Output {
             client,
             available,
             held,
-            total: available + held,
+            total: available
+                .checked_add(held)
+                .expect("balances are only updated when their total is representable"),
             locked,
+            stats,
         }
     }
 }
@@ -182,60 +361,92 @@ impl Into<Output> for (ClientId, ClientState) {
 mod tests {
     use super::*;
 
+    fn amount(s: &str) -> Amount {
+        s.parse().unwrap()
+    }
+
     macro_rules! maplit {
         ($($k:expr=>$v:expr),*) => {
             [$(($k,$v)),*].iter().copied().collect()
         };
     }
-    // In a production system, I would test i/o caused by handling error conditions as well.
+    /// Processes `tx` with the default policy and no events.
+    fn process(cs: &mut ClientState, tx: Transaction) {
+        cs.process_transaction(tx, DisputePolicy::default(), &mut NoopEvents)
+    }
+
     #[test]
     fn test_deposit() {
         let mut cs = ClientState::default();
 
-        cs.process_transaction(Transaction::Deposit {
-            client: ClientId(1),
-            tx: TransactionId(1),
-            amount: 3.,
-        });
+        process(
+            &mut cs,
+            Transaction::Deposit {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: amount("3"),
+            },
+        );
         assert_eq!(
             cs,
             ClientState {
-                available: 3.,
-                held: 0.,
+                available: amount("3"),
+                held: amount("0"),
                 locked: false,
-                deposits: maplit! {
+                transactions: maplit! {
                     TransactionId(1) =>
-                    DepositState {
-                        amount: 3.0,
-                        ty: DepositStateType::Normal
+                    TransactionState {
+                        kind: TransactionKind::Deposit,
+                        amount: amount("3"),
+                        ty: TransactionStateType::Normal,
+                        disputed: Amount::ZERO
                     }
-                }
+                },
+                stats: ClientStats {
+                    deposits: 1,
+                    deposited: amount("3"),
+                    last_tx: Some(TransactionId(1)),
+                    ..Default::default()
+                },
             }
         );
 
-        cs.process_transaction(Transaction::Deposit {
-            client: ClientId(1),
-            tx: TransactionId(2),
-            amount: 5.,
-        });
+        process(
+            &mut cs,
+            Transaction::Deposit {
+                client: ClientId(1),
+                tx: TransactionId(2),
+                amount: amount("5"),
+            },
+        );
         assert_eq!(
             cs,
             ClientState {
-                available: 8.,
-                held: 0.,
+                available: amount("8"),
+                held: amount("0"),
                 locked: false,
-                deposits: maplit! {
+                transactions: maplit! {
                     TransactionId(1) =>
-                    DepositState {
-                        amount: 3.0,
-                        ty: DepositStateType::Normal
+                    TransactionState {
+                        kind: TransactionKind::Deposit,
+                        amount: amount("3"),
+                        ty: TransactionStateType::Normal,
+                        disputed: Amount::ZERO
                     },
                     TransactionId(2) =>
-                    DepositState {
-                        amount: 5.0,
-                        ty: DepositStateType::Normal
+                    TransactionState {
+                        kind: TransactionKind::Deposit,
+                        amount: amount("5"),
+                        ty: TransactionStateType::Normal,
+                        disputed: Amount::ZERO
                     }
-                }
+                },
+                stats: ClientStats {
+                    deposits: 2,
+                    deposited: amount("8"),
+                    last_tx: Some(TransactionId(2)),
+                    ..Default::default()
+                },
             }
         );
     }
@@ -245,47 +456,68 @@ mod tests {
 
         // Withdraw empty account.
         let old_cs = cs.clone();
-        cs.process_transaction(Transaction::Withdrawal {
-            client: ClientId(1),
-            tx: TransactionId(1),
-            amount: 2.,
-        });
+        process(
+            &mut cs,
+            Transaction::Withdrawal {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: amount("2"),
+            },
+        );
         assert_eq!(cs, old_cs);
 
         // Successful withdrawal.
-        cs.process_transaction(Transaction::Deposit {
-            client: ClientId(1),
-            tx: TransactionId(2),
-            amount: 3.,
-        });
-        cs.process_transaction(Transaction::Withdrawal {
-            client: ClientId(1),
-            tx: TransactionId(3),
-            amount: 2.,
-        });
+        process(
+            &mut cs,
+            Transaction::Deposit {
+                client: ClientId(1),
+                tx: TransactionId(2),
+                amount: amount("3"),
+            },
+        );
+        process(
+            &mut cs,
+            Transaction::Withdrawal {
+                client: ClientId(1),
+                tx: TransactionId(3),
+                amount: amount("2"),
+            },
+        );
         assert_eq!(
             cs,
             ClientState {
-                available: 1.,
-                held: 0.,
+                available: amount("1"),
+                held: amount("0"),
                 locked: false,
-                deposits: maplit! {
+                transactions: maplit! {
                     TransactionId(2) =>
-                    DepositState {
-                        amount: 3.0,
-                        ty: DepositStateType::Normal
+                    TransactionState {
+                        kind: TransactionKind::Deposit,
+                        amount: amount("3"),
+                        ty: TransactionStateType::Normal,
+                        disputed: Amount::ZERO
                     }
-                }
+                },
+                stats: ClientStats {
+                    deposits: 1,
+                    deposited: amount("3"),
+                    withdrawn: amount("2"),
+                    last_tx: Some(TransactionId(3)),
+                    ..Default::default()
+                },
             }
         );
 
         // Too little funds.
         let old_cs = cs.clone();
-        cs.process_transaction(Transaction::Withdrawal {
-            client: ClientId(1),
-            tx: TransactionId(2),
-            amount: 2.,
-        });
+        process(
+            &mut cs,
+            Transaction::Withdrawal {
+                client: ClientId(1),
+                tx: TransactionId(2),
+                amount: amount("2"),
+            },
+        );
         assert_eq!(cs, old_cs);
     }
 
@@ -295,98 +527,154 @@ mod tests {
         let old_cs = cs.clone();
 
         // Dispute empty account
-        cs.process_transaction(Transaction::Dispute {
-            client: ClientId(1),
-            tx: TransactionId(1),
-        });
+        process(
+            &mut cs,
+            Transaction::Dispute {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: None,
+            },
+        );
         assert_eq!(cs, old_cs);
 
         // Dispute deposit
-        cs.process_transaction(Transaction::Deposit {
-            client: ClientId(1),
-            tx: TransactionId(1),
-            amount: 3.,
-        });
-        cs.process_transaction(Transaction::Dispute {
-            client: ClientId(1),
-            tx: TransactionId(1),
-        });
+        process(
+            &mut cs,
+            Transaction::Deposit {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: amount("3"),
+            },
+        );
+        process(
+            &mut cs,
+            Transaction::Dispute {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: None,
+            },
+        );
         assert_eq!(
             cs,
             ClientState {
-                available: 0.,
-                held: 3.,
+                available: amount("0"),
+                held: amount("3"),
                 locked: false,
-                deposits: maplit! {
+                transactions: maplit! {
                     TransactionId(1) =>
-                    DepositState {
-                        amount: 3.0,
-                        ty: DepositStateType::Disputed
+                    TransactionState {
+                        kind: TransactionKind::Deposit,
+                        amount: amount("3"),
+                        ty: TransactionStateType::Disputed,
+                        disputed: amount("3")
                     }
-                }
+                },
+                stats: ClientStats {
+                    deposits: 1,
+                    open_disputes: 1,
+                    deposited: amount("3"),
+                    last_tx: Some(TransactionId(1)),
+                    ..Default::default()
+                },
             }
         );
 
         // Dispute already disputed.
         let old_cs = cs.clone();
-        cs.process_transaction(Transaction::Dispute {
-            client: ClientId(1),
-            tx: TransactionId(1),
-        });
+        process(
+            &mut cs,
+            Transaction::Dispute {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: None,
+            },
+        );
         assert_eq!(cs, old_cs);
 
         // Deposit->withdraw->dispute results in negative available.
-        cs.process_transaction(Transaction::Deposit {
-            client: ClientId(1),
-            tx: TransactionId(2),
-            amount: 5.,
-        });
-        cs.process_transaction(Transaction::Withdrawal {
-            client: ClientId(1),
-            tx: TransactionId(3),
-            amount: 5.,
-        });
-        cs.process_transaction(Transaction::Dispute {
-            client: ClientId(1),
-            tx: TransactionId(2),
-        });
+        process(
+            &mut cs,
+            Transaction::Deposit {
+                client: ClientId(1),
+                tx: TransactionId(2),
+                amount: amount("5"),
+            },
+        );
+        process(
+            &mut cs,
+            Transaction::Withdrawal {
+                client: ClientId(1),
+                tx: TransactionId(3),
+                amount: amount("5"),
+            },
+        );
+        process(
+            &mut cs,
+            Transaction::Dispute {
+                client: ClientId(1),
+                tx: TransactionId(2),
+                amount: None,
+            },
+        );
         assert_eq!(
             cs,
             ClientState {
-                available: -5.,
-                held: 8.,
+                available: amount("-5"),
+                held: amount("8"),
                 locked: false,
-                deposits: maplit! {
+                transactions: maplit! {
                     TransactionId(1) =>
-                    DepositState {
-                        amount: 3.0,
-                        ty: DepositStateType::Disputed
+                    TransactionState {
+                        kind: TransactionKind::Deposit,
+                        amount: amount("3"),
+                        ty: TransactionStateType::Disputed,
+                        disputed: amount("3")
                     },
                     TransactionId(2) =>
-                    DepositState {
-                        amount: 5.0,
-                        ty: DepositStateType::Disputed
+                    TransactionState {
+                        kind: TransactionKind::Deposit,
+                        amount: amount("5"),
+                        ty: TransactionStateType::Disputed,
+                        disputed: amount("5")
                     }
-                }
+                },
+                stats: ClientStats {
+                    deposits: 2,
+                    open_disputes: 2,
+                    deposited: amount("8"),
+                    withdrawn: amount("5"),
+                    last_tx: Some(TransactionId(2)),
+                    ..Default::default()
+                },
             }
         );
         // Dispute withdrawal
-        cs.process_transaction(Transaction::Deposit {
-            client: ClientId(1),
-            tx: TransactionId(5),
-            amount: 5.,
-        });
-        cs.process_transaction(Transaction::Withdrawal {
-            client: ClientId(1),
-            tx: TransactionId(6),
-            amount: 5.,
-        });
+        process(
+            &mut cs,
+            Transaction::Deposit {
+                client: ClientId(1),
+                tx: TransactionId(5),
+                amount: amount("5"),
+            },
+        );
+        process(
+            &mut cs,
+            Transaction::Withdrawal {
+                client: ClientId(1),
+                tx: TransactionId(6),
+                amount: amount("5"),
+            },
+        );
 
         let old_cs = cs.clone();
-        cs.process_transaction(Transaction::Dispute {
-            client: ClientId(1),
-            tx: TransactionId(6),
-        });
+        process(
+            &mut cs,
+            Transaction::Dispute {
+                client: ClientId(1),
+                tx: TransactionId(6),
+                amount: None,
+            },
+        );
         assert_eq!(cs, old_cs)
     }
 
@@ -396,63 +684,104 @@ mod tests {
         let old_cs = cs.clone();
 
         // Resolve empty account
-        cs.process_transaction(Transaction::Resolve {
-            client: ClientId(1),
-            tx: TransactionId(1),
-        });
+        process(
+            &mut cs,
+            Transaction::Resolve {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: None,
+            },
+        );
         assert_eq!(cs, old_cs);
 
         // Resolve deposit
-        cs.process_transaction(Transaction::Deposit {
-            client: ClientId(1),
-            tx: TransactionId(1),
-            amount: 3.,
-        });
+        process(
+            &mut cs,
+            Transaction::Deposit {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: amount("3"),
+            },
+        );
         let old_cs = cs.clone();
-        cs.process_transaction(Transaction::Resolve {
-            client: ClientId(1),
-            tx: TransactionId(1),
-        });
+        process(
+            &mut cs,
+            Transaction::Resolve {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: None,
+            },
+        );
         assert_eq!(cs, old_cs);
 
         // Resolve dispute
-        cs.process_transaction(Transaction::Dispute {
-            client: ClientId(1),
-            tx: TransactionId(1),
-        });
-        cs.process_transaction(Transaction::Resolve {
-            client: ClientId(1),
-            tx: TransactionId(1),
-        });
+        process(
+            &mut cs,
+            Transaction::Dispute {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: None,
+            },
+        );
+        process(
+            &mut cs,
+            Transaction::Resolve {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: None,
+            },
+        );
         assert_eq!(cs, old_cs);
 
         // Resolve chargeback
-        cs.process_transaction(Transaction::Dispute {
-            client: ClientId(1),
-            tx: TransactionId(1),
-        });
-        cs.process_transaction(Transaction::ChargeBack {
-            client: ClientId(1),
-            tx: TransactionId(1),
-        });
-        cs.process_transaction(Transaction::Resolve {
-            client: ClientId(1),
-            tx: TransactionId(1),
-        });
+        process(
+            &mut cs,
+            Transaction::Dispute {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: None,
+            },
+        );
+        process(
+            &mut cs,
+            Transaction::ChargeBack {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: None,
+            },
+        );
+        process(
+            &mut cs,
+            Transaction::Resolve {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: None,
+            },
+        );
 
         assert_eq!(
             cs,
             ClientState {
-                available: 0.,
-                held: 0.,
+                available: amount("0"),
+                held: amount("0"),
                 locked: true,
-                deposits: maplit! {
+                transactions: maplit! {
                     TransactionId(1) =>
-                    DepositState {
-                        amount: 3.0,
-                        ty: DepositStateType::ChargedBack
+                    TransactionState {
+                        kind: TransactionKind::Deposit,
+                        amount: amount("3"),
+                        ty: TransactionStateType::ChargedBack,
+                        disputed: Amount::ZERO
                     }
-                }
+                },
+                stats: ClientStats {
+                    deposits: 1,
+                    chargebacks: 1,
+                    deposited: amount("3"),
+                    last_tx: Some(TransactionId(1)),
+                    lock_reason: Some(LockReason::ChargeBack),
+                    ..Default::default()
+                },
             }
         );
     }
@@ -463,47 +792,76 @@ mod tests {
         let old_cs = cs.clone();
 
         // Chargeback empty account
-        cs.process_transaction(Transaction::ChargeBack {
-            client: ClientId(1),
-            tx: TransactionId(1),
-        });
+        process(
+            &mut cs,
+            Transaction::ChargeBack {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: None,
+            },
+        );
         assert_eq!(cs, old_cs);
 
         // Chargeback deposit
-        cs.process_transaction(Transaction::Deposit {
-            client: ClientId(1),
-            tx: TransactionId(1),
-            amount: 3.,
-        });
+        process(
+            &mut cs,
+            Transaction::Deposit {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: amount("3"),
+            },
+        );
         let old_cs = cs.clone();
-        cs.process_transaction(Transaction::ChargeBack {
-            client: ClientId(1),
-            tx: TransactionId(1),
-        });
+        process(
+            &mut cs,
+            Transaction::ChargeBack {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: None,
+            },
+        );
         assert_eq!(cs, old_cs);
 
         // Chargeback disputed
-        cs.process_transaction(Transaction::Dispute {
-            client: ClientId(1),
-            tx: TransactionId(1),
-        });
-        cs.process_transaction(Transaction::ChargeBack {
-            client: ClientId(1),
-            tx: TransactionId(1),
-        });
+        process(
+            &mut cs,
+            Transaction::Dispute {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: None,
+            },
+        );
+        process(
+            &mut cs,
+            Transaction::ChargeBack {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: None,
+            },
+        );
         assert_eq!(
             cs,
             ClientState {
-                available: 0.,
-                held: 0.,
+                available: amount("0"),
+                held: amount("0"),
                 locked: true,
-                deposits: maplit! {
+                transactions: maplit! {
                     TransactionId(1) =>
-                    DepositState {
-                        amount: 3.0,
-                        ty: DepositStateType::ChargedBack
+                    TransactionState {
+                        kind: TransactionKind::Deposit,
+                        amount: amount("3"),
+                        ty: TransactionStateType::ChargedBack,
+                        disputed: Amount::ZERO
                     }
-                }
+                },
+                stats: ClientStats {
+                    deposits: 1,
+                    chargebacks: 1,
+                    deposited: amount("3"),
+                    last_tx: Some(TransactionId(1)),
+                    lock_reason: Some(LockReason::ChargeBack),
+                    ..Default::default()
+                },
             }
         );
 
@@ -511,40 +869,58 @@ mod tests {
         // should not alter the state.
         let old_cs = cs.clone();
         // Deposit after ChargeBack
-        cs.process_transaction(Transaction::Deposit {
-            client: ClientId(1),
-            tx: TransactionId(4),
-            amount: 8.,
-        });
+        process(
+            &mut cs,
+            Transaction::Deposit {
+                client: ClientId(1),
+                tx: TransactionId(4),
+                amount: amount("8"),
+            },
+        );
         assert_eq!(cs, old_cs);
 
         // Withdraw after ChargeBack
-        cs.process_transaction(Transaction::Withdrawal {
-            client: ClientId(1),
-            tx: TransactionId(5),
-            amount: 8.,
-        });
+        process(
+            &mut cs,
+            Transaction::Withdrawal {
+                client: ClientId(1),
+                tx: TransactionId(5),
+                amount: amount("8"),
+            },
+        );
         assert_eq!(cs, old_cs);
 
         // Dispute after ChargeBack
-        cs.process_transaction(Transaction::Dispute {
-            client: ClientId(1),
-            tx: TransactionId(1),
-        });
+        process(
+            &mut cs,
+            Transaction::Dispute {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: None,
+            },
+        );
         assert_eq!(cs, old_cs);
 
         // Resolve after ChargeBack
-        cs.process_transaction(Transaction::Resolve {
-            client: ClientId(1),
-            tx: TransactionId(1),
-        });
+        process(
+            &mut cs,
+            Transaction::Resolve {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: None,
+            },
+        );
         assert_eq!(cs, old_cs);
 
         // ChargeBack after ChargeBack
-        cs.process_transaction(Transaction::ChargeBack {
-            client: ClientId(1),
-            tx: TransactionId(1),
-        });
+        process(
+            &mut cs,
+            Transaction::ChargeBack {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: None,
+            },
+        );
         assert_eq!(cs, old_cs);
     }
 
@@ -554,40 +930,579 @@ mod tests {
 
         // Deposit->withdraw->dispute->chargeback leads to
         // negative available
-        cs.process_transaction(Transaction::Deposit {
-            client: ClientId(1),
-            tx: TransactionId(1),
-            amount: 3.0,
-        });
-        cs.process_transaction(Transaction::Withdrawal {
-            client: ClientId(1),
-            tx: TransactionId(2),
-            amount: 2.0,
-        });
-        cs.process_transaction(Transaction::Dispute {
-            client: ClientId(1),
-            tx: TransactionId(1),
-        });
-        cs.process_transaction(Transaction::ChargeBack {
-            client: ClientId(1),
-            tx: TransactionId(1),
-        });
-        println!("cs:{:?};",cs);
+        process(
+            &mut cs,
+            Transaction::Deposit {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: amount("3"),
+            },
+        );
+        process(
+            &mut cs,
+            Transaction::Withdrawal {
+                client: ClientId(1),
+                tx: TransactionId(2),
+                amount: amount("2"),
+            },
+        );
+        process(
+            &mut cs,
+            Transaction::Dispute {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: None,
+            },
+        );
+        process(
+            &mut cs,
+            Transaction::ChargeBack {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: None,
+            },
+        );
+        println!("cs:{:?};", cs);
         assert_eq!(
             cs,
             ClientState {
-                available: -2.,
-                held: 0.,
+                available: amount("-2"),
+                held: amount("0"),
                 locked: true,
-                deposits: maplit! {
+                transactions: maplit! {
                     TransactionId(1) =>
-                    DepositState {
-                        amount: 3.0,
-                        ty: DepositStateType::ChargedBack
+                    TransactionState {
+                        kind: TransactionKind::Deposit,
+                        amount: amount("3"),
+                        ty: TransactionStateType::ChargedBack,
+                        disputed: Amount::ZERO
                     }
-                }
+                },
+                stats: ClientStats {
+                    deposits: 1,
+                    chargebacks: 1,
+                    deposited: amount("3"),
+                    withdrawn: amount("2"),
+                    last_tx: Some(TransactionId(1)),
+                    lock_reason: Some(LockReason::ChargeBack),
+                    ..Default::default()
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn test_dispute_withdrawal() {
+        let policy = DisputePolicy::DepositsAndWithdrawals;
+        let mut cs = ClientState::default();
+
+        cs.process_transaction(
+            Transaction::Deposit {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: amount("5"),
+            },
+            policy,
+            &mut NoopEvents,
+        );
+        cs.process_transaction(
+            Transaction::Withdrawal {
+                client: ClientId(1),
+                tx: TransactionId(2),
+                amount: amount("3"),
+            },
+            policy,
+            &mut NoopEvents,
+        );
+        let withdrawn = cs.clone();
+
+        // Dispute withdrawal holds the amount.
+        cs.process_transaction(
+            Transaction::Dispute {
+                client: ClientId(1),
+                tx: TransactionId(2),
+                amount: None,
+            },
+            policy,
+            &mut NoopEvents,
+        );
+        assert_eq!(
+            cs,
+            ClientState {
+                available: amount("2"),
+                held: amount("3"),
+                locked: false,
+                transactions: maplit! {
+                    TransactionId(1) =>
+                    TransactionState {
+                        kind: TransactionKind::Deposit,
+                        amount: amount("5"),
+                        ty: TransactionStateType::Normal,
+                        disputed: Amount::ZERO
+                    },
+                    TransactionId(2) =>
+                    TransactionState {
+                        kind: TransactionKind::Withdrawal,
+                        amount: amount("3"),
+                        ty: TransactionStateType::Disputed,
+                        disputed: amount("3")
+                    }
+                },
+                stats: ClientStats {
+                    deposits: 1,
+                    open_disputes: 1,
+                    deposited: amount("5"),
+                    withdrawn: amount("3"),
+                    last_tx: Some(TransactionId(2)),
+                    ..Default::default()
+                },
             }
         );
+
+        // Resolve withdrawal releases the hold.
+        cs.process_transaction(
+            Transaction::Resolve {
+                client: ClientId(1),
+                tx: TransactionId(2),
+                amount: None,
+            },
+            policy,
+            &mut NoopEvents,
+        );
+        assert_eq!(cs, withdrawn);
+
+        // Chargeback withdrawal returns the amount to available.
+        cs.process_transaction(
+            Transaction::Dispute {
+                client: ClientId(1),
+                tx: TransactionId(2),
+                amount: None,
+            },
+            policy,
+            &mut NoopEvents,
+        );
+        cs.process_transaction(
+            Transaction::ChargeBack {
+                client: ClientId(1),
+                tx: TransactionId(2),
+                amount: None,
+            },
+            policy,
+            &mut NoopEvents,
+        );
+        assert_eq!(
+            cs,
+            ClientState {
+                available: amount("5"),
+                held: amount("0"),
+                locked: true,
+                transactions: maplit! {
+                    TransactionId(1) =>
+                    TransactionState {
+                        kind: TransactionKind::Deposit,
+                        amount: amount("5"),
+                        ty: TransactionStateType::Normal,
+                        disputed: Amount::ZERO
+                    },
+                    TransactionId(2) =>
+                    TransactionState {
+                        kind: TransactionKind::Withdrawal,
+                        amount: amount("3"),
+                        ty: TransactionStateType::ChargedBack,
+                        disputed: Amount::ZERO
+                    }
+                },
+                stats: ClientStats {
+                    deposits: 1,
+                    chargebacks: 1,
+                    deposited: amount("5"),
+                    withdrawn: amount("3"),
+                    last_tx: Some(TransactionId(2)),
+                    lock_reason: Some(LockReason::ChargeBack),
+                    ..Default::default()
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn test_partial_dispute() {
+        let mut cs = ClientState::default();
+        process(
+            &mut cs,
+            Transaction::Deposit {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: amount("10"),
+            },
+        );
+
+        // Two partial disputes.
+        process(
+            &mut cs,
+            Transaction::Dispute {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: Some(amount("4")),
+            },
+        );
+        process(
+            &mut cs,
+            Transaction::Dispute {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: Some(amount("5")),
+            },
+        );
+        assert_eq!(
+            cs,
+            ClientState {
+                available: amount("1"),
+                held: amount("9"),
+                locked: false,
+                transactions: maplit! {
+                    TransactionId(1) =>
+                    TransactionState {
+                        kind: TransactionKind::Deposit,
+                        amount: amount("10"),
+                        ty: TransactionStateType::Disputed,
+                        disputed: amount("9")
+                    }
+                },
+                stats: ClientStats {
+                    deposits: 1,
+                    open_disputes: 1,
+                    deposited: amount("10"),
+                    last_tx: Some(TransactionId(1)),
+                    ..Default::default()
+                },
+            }
+        );
+
+        // Disputes can't exceed the original amount.
+        let old_cs = cs.clone();
+        process(
+            &mut cs,
+            Transaction::Dispute {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: Some(amount("2")),
+            },
+        );
+        assert_eq!(cs, old_cs);
+
+        // Resolves can't exceed the disputed amount.
+        process(
+            &mut cs,
+            Transaction::Resolve {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: Some(amount("10")),
+            },
+        );
+        assert_eq!(cs, old_cs);
+
+        // Partial resolve.
+        process(
+            &mut cs,
+            Transaction::Resolve {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: Some(amount("3")),
+            },
+        );
+        assert_eq!(cs.available, amount("4"));
+        assert_eq!(cs.held, amount("6"));
+
+        // A full dispute takes the rest of the deposit.
+        process(
+            &mut cs,
+            Transaction::Dispute {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: None,
+            },
+        );
+        assert_eq!(cs.available, amount("0"));
+        assert_eq!(cs.held, amount("10"));
+
+        // Partial chargeback releases the rest of the dispute.
+        process(
+            &mut cs,
+            Transaction::ChargeBack {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: Some(amount("2.5")),
+            },
+        );
+        assert_eq!(
+            cs,
+            ClientState {
+                available: amount("7.5"),
+                held: amount("0"),
+                locked: true,
+                transactions: maplit! {
+                    TransactionId(1) =>
+                    TransactionState {
+                        kind: TransactionKind::Deposit,
+                        amount: amount("10"),
+                        ty: TransactionStateType::ChargedBack,
+                        disputed: Amount::ZERO
+                    }
+                },
+                stats: ClientStats {
+                    deposits: 1,
+                    chargebacks: 1,
+                    deposited: amount("10"),
+                    last_tx: Some(TransactionId(1)),
+                    lock_reason: Some(LockReason::ChargeBack),
+                    ..Default::default()
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn test_freeze_unlock() {
+        let mut cs = ClientState::default();
+        process(
+            &mut cs,
+            Transaction::Deposit {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: amount("3"),
+            },
+        );
+
+        // Unlock an account that is not locked.
+        let old_cs = cs.clone();
+        process(
+            &mut cs,
+            Transaction::Unlock {
+                client: ClientId(1),
+                tx: TransactionId(2),
+                audit: None,
+            },
+        );
+        assert_eq!(cs, old_cs);
+
+        // Freeze.
+        process(
+            &mut cs,
+            Transaction::Freeze {
+                client: ClientId(1),
+                tx: TransactionId(3),
+                audit: Some("alice: investigation".to_string()),
+            },
+        );
+        assert!(cs.locked);
+        assert_eq!(cs.stats.lock_reason, Some(LockReason::Freeze));
+
+        // Frozen accounts reject transactions.
+        let old_cs = cs.clone();
+        process(
+            &mut cs,
+            Transaction::Deposit {
+                client: ClientId(1),
+                tx: TransactionId(4),
+                amount: amount("3"),
+            },
+        );
+        assert_eq!(cs, old_cs);
+
+        // Unlock after freeze.
+        process(
+            &mut cs,
+            Transaction::Unlock {
+                client: ClientId(1),
+                tx: TransactionId(5),
+                audit: Some("alice: cleared".to_string()),
+            },
+        );
+        assert!(!cs.locked);
+
+        // Unlock after chargeback.
+        process(
+            &mut cs,
+            Transaction::Dispute {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: None,
+            },
+        );
+        process(
+            &mut cs,
+            Transaction::ChargeBack {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: None,
+            },
+        );
+        assert!(cs.locked);
+        assert_eq!(cs.stats.lock_reason, Some(LockReason::ChargeBack));
+        process(
+            &mut cs,
+            Transaction::Unlock {
+                client: ClientId(1),
+                tx: TransactionId(6),
+                audit: None,
+            },
+        );
+        process(
+            &mut cs,
+            Transaction::Deposit {
+                client: ClientId(1),
+                tx: TransactionId(7),
+                amount: amount("2"),
+            },
+        );
+        assert_eq!(
+            cs,
+            ClientState {
+                available: amount("2"),
+                held: amount("0"),
+                locked: false,
+                transactions: maplit! {
+                    TransactionId(1) =>
+                    TransactionState {
+                        kind: TransactionKind::Deposit,
+                        amount: amount("3"),
+                        ty: TransactionStateType::ChargedBack,
+                        disputed: Amount::ZERO
+                    },
+                    TransactionId(7) =>
+                    TransactionState {
+                        kind: TransactionKind::Deposit,
+                        amount: amount("2"),
+                        ty: TransactionStateType::Normal,
+                        disputed: Amount::ZERO
+                    }
+                },
+                stats: ClientStats {
+                    deposits: 2,
+                    chargebacks: 1,
+                    deposited: amount("5"),
+                    last_tx: Some(TransactionId(7)),
+                    ..Default::default()
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn test_events() {
+        let mut cs = ClientState::default();
+        let mut events = MemoryEvents::default();
+        let client = ClientId(1);
+        let policy = DisputePolicy::default();
+
+        for tx in [
+            Transaction::Deposit {
+                client,
+                tx: TransactionId(1),
+                amount: amount("3"),
+            },
+            Transaction::Withdrawal {
+                client,
+                tx: TransactionId(2),
+                amount: amount("5"),
+            },
+            Transaction::Resolve {
+                client,
+                tx: TransactionId(1),
+                amount: None,
+            },
+            Transaction::Dispute {
+                client,
+                tx: TransactionId(1),
+                amount: Some(amount("4")),
+            },
+            Transaction::Dispute {
+                client,
+                tx: TransactionId(1),
+                amount: Some(amount("1")),
+            },
+            Transaction::Dispute {
+                client,
+                tx: TransactionId(9),
+                amount: None,
+            },
+            Transaction::ChargeBack {
+                client,
+                tx: TransactionId(1),
+                amount: None,
+            },
+            Transaction::Deposit {
+                client,
+                tx: TransactionId(3),
+                amount: amount("3"),
+            },
+            Transaction::Unlock {
+                client,
+                tx: TransactionId(4),
+                audit: Some("alice".to_string()),
+            },
+            Transaction::Unlock {
+                client,
+                tx: TransactionId(5),
+                audit: None,
+            },
+        ] {
+            cs.process_transaction(tx, policy, &mut events);
+        }
+
+        assert_eq!(
+            events.events,
+            vec![
+                LedgerEvent::Deposited(Deposited {
+                    client,
+                    tx: TransactionId(1),
+                    amount: amount("3")
+                }),
+                LedgerEvent::InsufficientFunds(InsufficientFunds {
+                    client,
+                    tx: TransactionId(2),
+                    amount: amount("5"),
+                    available: amount("3")
+                }),
+                LedgerEvent::NotDisputed(NotDisputed {
+                    client,
+                    tx: TransactionId(1)
+                }),
+                LedgerEvent::InvalidDisputeAmount(InvalidDisputeAmount {
+                    client,
+                    tx: TransactionId(1),
+                    amount: amount("4")
+                }),
+                LedgerEvent::Disputed(Disputed {
+                    client,
+                    tx: TransactionId(1),
+                    amount: amount("1")
+                }),
+                LedgerEvent::NonExistentTransaction(NonExistentTransaction {
+                    client,
+                    tx: TransactionId(9)
+                }),
+                LedgerEvent::ChargedBack(ChargedBack {
+                    client,
+                    tx: TransactionId(1),
+                    amount: amount("1")
+                }),
+                LedgerEvent::AccountLocked(AccountLocked {
+                    client,
+                    tx: TransactionId(3)
+                }),
+                LedgerEvent::Unlocked(Unlocked {
+                    client,
+                    tx: TransactionId(4),
+                    audit: Some("alice".to_string())
+                }),
+                LedgerEvent::NotLocked(NotLocked {
+                    client,
+                    tx: TransactionId(5)
+                }),
+            ]
+        );
     }
     use better_macro::println;
 }
@@ -0,0 +1,125 @@
+use async_compression::futures::{
+    bufread::{GzipDecoder, ZstdDecoder},
+    write::{GzipEncoder, ZstdEncoder},
+};
+use smol::{
+    fs::File,
+    io::{AsyncBufRead, AsyncWrite, BufWriter},
+};
+use std::io;
+
+/// How a file is compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+impl Compression {
+    /// Number of bytes [Compression::detect] needs to recognize every compression.
+    pub const DETECT_LEN: usize = 4;
+
+    /// Detects the compression of a file that starts with `bytes`.
+    ///
+    /// `bytes` needs to hold at least the first [DETECT_LEN](Compression::DETECT_LEN) bytes of the file
+    /// to detect zstd.
+    pub fn detect(bytes: &[u8]) -> Self {
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+    /// Picks the compression of `path` from its extension, `.gz` or `.zst`.
+    pub fn from_path(path: &str) -> Self {
+        if path.ends_with(".gz") {
+            Compression::Gzip
+        } else if path.ends_with(".zst") {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// Returns a streaming decoder for the gzip compressed `read`.
+///
+/// Concatenated gzip members are decoded one after another, like `zcat` does.
+pub fn gzip_decoder<R: AsyncBufRead>(read: R) -> GzipDecoder<R> {
+    let mut decoder = GzipDecoder::new(read);
+    decoder.multiple_members(true);
+    decoder
+}
+
+/// Returns a streaming decoder for the zstd compressed `read`.
+///
+/// Concatenated zstd frames are decoded one after another.
+pub fn zstd_decoder<R: AsyncBufRead>(read: R) -> ZstdDecoder<R> {
+    let mut decoder = ZstdDecoder::new(read);
+    decoder.multiple_members(true);
+    decoder
+}
+
+/// Creates the file at `path` and returns a buffered writer that compresses what is written to it
+/// according to the extension of `path`.
+///
+/// The writer must be closed to write the end of the compressed stream.
+pub async fn create(path: &str) -> io::Result<BufWriter<Box<dyn AsyncWrite + Unpin>>> {
+    let file = File::create(path).await?;
+    let wri: Box<dyn AsyncWrite + Unpin> = match Compression::from_path(path) {
+        Compression::None => Box::new(file),
+        Compression::Gzip => Box::new(GzipEncoder::new(file)),
+        Compression::Zstd => Box::new(ZstdEncoder::new(file)),
+    };
+    Ok(BufWriter::new(wri))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use smol::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(Compression::detect(b"type,client"), Compression::None);
+        assert_eq!(Compression::detect(b""), Compression::None);
+        assert_eq!(Compression::detect(&[0x1f, 0x8b, 8, 0]), Compression::Gzip);
+        assert_eq!(
+            Compression::detect(&[0x28, 0xb5, 0x2f, 0xfd, 0]),
+            Compression::Zstd
+        );
+        assert_eq!(Compression::from_path("a/b.csv.gz"), Compression::Gzip);
+        assert_eq!(Compression::from_path("b.csv.zst"), Compression::Zstd);
+        assert_eq!(Compression::from_path("b.csv"), Compression::None);
+    }
+
+    #[smol_potat::test]
+    async fn test_round_trip() {
+        let text = "type,client,tx,amount\ndeposit,1,1,1.5\n".repeat(1000);
+        for extension in ["csv", "csv.gz", "csv.zst"].iter() {
+            let path = std::env::temp_dir().join(format!(
+                "compression-{}.{}",
+                std::process::id(),
+                extension
+            ));
+            let path = path.to_str().unwrap();
+            let mut wri = create(path).await.unwrap();
+            wri.write_all(text.as_bytes()).await.unwrap();
+            wri.close().await.unwrap();
+
+            let bytes = std::fs::read(path).unwrap();
+            std::fs::remove_file(path).unwrap();
+            let mut read = String::new();
+            match Compression::detect(&bytes) {
+                Compression::None => BufReader::new(&bytes[..]).read_to_string(&mut read).await,
+                Compression::Gzip => gzip_decoder(&bytes[..]).read_to_string(&mut read).await,
+                Compression::Zstd => zstd_decoder(&bytes[..]).read_to_string(&mut read).await,
+            }
+            .unwrap();
+            assert_eq!(read, text, "{}", extension);
+            assert_eq!(Compression::detect(&bytes), Compression::from_path(path));
+        }
+    }
+}
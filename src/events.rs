@@ -0,0 +1,367 @@
+use parse_display::Display;
+use std::{
+    fmt::{self, Formatter},
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    amount::Amount,
+    io::{ClientId, TransactionId},
+};
+
+#[derive(Display, Debug, Clone, PartialEq)]
+#[display("Client: {client} deposited {amount} in transaction {tx}.")]
+/// Funds were deposited.
+pub struct Deposited {
+    pub client: ClientId,
+    pub tx: TransactionId,
+    pub amount: Amount,
+}
+#[derive(Display, Debug, Clone, PartialEq)]
+#[display("Client: {client} withdrew {amount} in transaction {tx}.")]
+/// Funds were withdrawn.
+pub struct Withdrawn {
+    pub client: ClientId,
+    pub tx: TransactionId,
+    pub amount: Amount,
+}
+#[derive(Display, Debug, Clone, PartialEq)]
+#[display("Client: {client} disputed {amount} of transaction {tx}.")]
+/// Part or all of a transaction was disputed and its amount held.
+pub struct Disputed {
+    pub client: ClientId,
+    pub tx: TransactionId,
+    pub amount: Amount,
+}
+#[derive(Display, Debug, Clone, PartialEq)]
+#[display("Client: {client} resolved {amount} of the dispute of transaction {tx}.")]
+/// Part or all of a dispute was resolved and its amount released.
+pub struct Resolved {
+    pub client: ClientId,
+    pub tx: TransactionId,
+    pub amount: Amount,
+}
+// In a production system this would probably send a notification to other services which would
+// contact the user and the customer support team.
+#[derive(Display, Debug, Clone, PartialEq)]
+#[display(
+    "Client: {client} is locked after issuing a chargeback of {amount} for transaction: {tx}."
+)]
+/// A dispute was charged back and the account locked.
+pub struct ChargedBack {
+    pub client: ClientId,
+    pub tx: TransactionId,
+    pub amount: Amount,
+}
+// In a production system these would be written to an audit log.
+#[derive(Debug, Clone, PartialEq)]
+/// The account was locked by an administrative freeze.
+pub struct Frozen {
+    pub client: ClientId,
+    pub tx: TransactionId,
+    pub audit: Option<String>,
+}
+#[derive(Debug, Clone, PartialEq)]
+/// The account was unlocked by an administrative unlock.
+pub struct Unlocked {
+    pub client: ClientId,
+    pub tx: TransactionId,
+    pub audit: Option<String>,
+}
+
+#[derive(Display, Debug, Clone, PartialEq)]
+#[display("Client: {client} attempted to withdraw {amount} in transaction {tx} while only {available} were available in their account.")]
+/// Rejected a withdrawal bigger than the available funds.
+pub struct InsufficientFunds {
+    pub client: ClientId,
+    pub tx: TransactionId,
+    pub amount: Amount,
+    pub available: Amount,
+}
+#[derive(Display, Debug, Clone, PartialEq)]
+#[display(
+    "Client: {client} attempted to dispute transaction {tx} which had already been disputed."
+)]
+/// Rejected a dispute of a transaction with nothing left to dispute.
+pub struct AlreadyDisputed {
+    pub client: ClientId,
+    pub tx: TransactionId,
+}
+#[derive(Display, Debug, Clone, PartialEq)]
+#[display(
+    "Client: {client} attempted to resolve or charge back transaction {tx} which is not disputed."
+)]
+/// Rejected a resolve or chargeback of a transaction that is not disputed.
+pub struct NotDisputed {
+    pub client: ClientId,
+    pub tx: TransactionId,
+}
+#[derive(Display, Debug, Clone, PartialEq)]
+#[display("Client: {client} attempted to dispute a non existent or undisputable transaction {tx}.")]
+/// Rejected a dispute, resolve or chargeback of a transaction that doesn't exist or can't be disputed.
+pub struct NonExistentTransaction {
+    pub client: ClientId,
+    pub tx: TransactionId,
+}
+#[derive(Display, Debug, Clone, PartialEq)]
+#[display("Client: {client} attempted to dispute, resolve or charge back {amount} of transaction {tx} which is more than allowed.")]
+/// Rejected a dispute, resolve or chargeback for more than allowed.
+pub struct InvalidDisputeAmount {
+    pub client: ClientId,
+    pub tx: TransactionId,
+    pub amount: Amount,
+}
+#[derive(Display, Debug, Clone, PartialEq)]
+#[display("Client: {client} issued transaction {tx} which would overflow the account balance.")]
+/// Rejected a transaction that would overflow a balance.
+pub struct Overflow {
+    pub client: ClientId,
+    pub tx: TransactionId,
+}
+#[derive(Display, Debug, Clone, PartialEq)]
+#[display("Client: {client} can't be unlocked by transaction {tx} because it is not locked.")]
+/// Rejected an unlock of an account that is not locked.
+pub struct NotLocked {
+    pub client: ClientId,
+    pub tx: TransactionId,
+}
+#[derive(Display, Debug, Clone, PartialEq)]
+#[display("Client: {client} issued transaction {tx} while their account is locked.")]
+/// Rejected a transaction of a locked account.
+pub struct AccountLocked {
+    pub client: ClientId,
+    pub tx: TransactionId,
+}
+// In a production system this would be reported to the upstream that resent the transaction.
+#[derive(Display, Debug, Clone, PartialEq)]
+#[display(
+    "Client: {client} issued transaction {tx} which reuses the id of an existing transaction."
+)]
+/// Rejected a transaction that reuses the id of a previous deposit, withdrawal, freeze or unlock.
+pub struct DuplicateTransaction {
+    pub client: ClientId,
+    pub tx: TransactionId,
+}
+
+impl fmt::Display for Frozen {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Client: {} was frozen by transaction {}: {}.",
+            self.client,
+            self.tx,
+            self.audit.as_deref().unwrap_or("no audit information")
+        )
+    }
+}
+impl fmt::Display for Unlocked {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Client: {} was unlocked by transaction {}: {}.",
+            self.client,
+            self.tx,
+            self.audit.as_deref().unwrap_or("no audit information")
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// Represents every state change and rejection that can happen while processing transactions.
+pub enum LedgerEvent {
+    Deposited(Deposited),
+    Withdrawn(Withdrawn),
+    Disputed(Disputed),
+    Resolved(Resolved),
+    ChargedBack(ChargedBack),
+    Frozen(Frozen),
+    Unlocked(Unlocked),
+    InsufficientFunds(InsufficientFunds),
+    AlreadyDisputed(AlreadyDisputed),
+    NotDisputed(NotDisputed),
+    NonExistentTransaction(NonExistentTransaction),
+    InvalidDisputeAmount(InvalidDisputeAmount),
+    Overflow(Overflow),
+    NotLocked(NotLocked),
+    AccountLocked(AccountLocked),
+    DuplicateTransaction(DuplicateTransaction),
+}
+impl fmt::Display for LedgerEvent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        use LedgerEvent::*;
+        match self {
+            Deposited(event) => event.fmt(f),
+            Withdrawn(event) => event.fmt(f),
+            Disputed(event) => event.fmt(f),
+            Resolved(event) => event.fmt(f),
+            ChargedBack(event) => event.fmt(f),
+            Frozen(event) => event.fmt(f),
+            Unlocked(event) => event.fmt(f),
+            InsufficientFunds(event) => event.fmt(f),
+            AlreadyDisputed(event) => event.fmt(f),
+            NotDisputed(event) => event.fmt(f),
+            NonExistentTransaction(event) => event.fmt(f),
+            InvalidDisputeAmount(event) => event.fmt(f),
+            Overflow(event) => event.fmt(f),
+            NotLocked(event) => event.fmt(f),
+            AccountLocked(event) => event.fmt(f),
+            DuplicateTransaction(event) => event.fmt(f),
+        }
+    }
+}
+impl LedgerEvent {
+    /// Returns the reason why the transaction was rejected, or [None] if the event is not a rejection.
+    pub fn rejection(&self) -> Option<Rejection> {
+        use LedgerEvent::*;
+        Some(match self {
+            Deposited(_) | Withdrawn(_) | Disputed(_) | Resolved(_) | ChargedBack(_)
+            | Frozen(_) | Unlocked(_) => return None,
+            InsufficientFunds(_) => Rejection::InsufficientFunds,
+            AlreadyDisputed(_) => Rejection::AlreadyDisputed,
+            NotDisputed(_) => Rejection::NotDisputed,
+            NonExistentTransaction(_) => Rejection::NonExistentTransaction,
+            InvalidDisputeAmount(_) => Rejection::InvalidDisputeAmount,
+            Overflow(_) => Rejection::Overflow,
+            NotLocked(_) => Rejection::NotLocked,
+            AccountLocked(_) => Rejection::AccountLocked,
+            DuplicateTransaction(_) => Rejection::DuplicateTransaction,
+        })
+    }
+    /// Returns true if the event means that a transaction was rejected.
+    pub fn is_rejection(&self) -> bool {
+        self.rejection().is_some()
+    }
+    /// Returns true if the event is a rejection or changes whether the account is locked.
+    pub fn is_notable(&self) -> bool {
+        use LedgerEvent::*;
+        self.is_rejection() || matches!(self, ChargedBack(_) | Frozen(_) | Unlocked(_))
+    }
+}
+
+#[derive(Display, Debug, Clone, Copy, PartialEq, Eq)]
+#[display(style = "snake_case")]
+/// Machine readable reason why a transaction was rejected, one per rejection [LedgerEvent].
+pub enum Rejection {
+    InsufficientFunds,
+    AlreadyDisputed,
+    NotDisputed,
+    NonExistentTransaction,
+    InvalidDisputeAmount,
+    Overflow,
+    NotLocked,
+    AccountLocked,
+    DuplicateTransaction,
+}
+
+/// Receives the [LedgerEvents](LedgerEvent) produced while processing transactions.
+///
+/// In a production system, sinks would submit anonymized structured logs and notifications to other services.
+pub trait LedgerEvents {
+    /// Handles a single event.
+    fn record(&mut self, event: LedgerEvent);
+    /// Makes sure every recorded event has been written out.
+    ///
+    /// Returns the first error encountered while recording events, if any.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// Ignores every event, compiles down to nothing.
+pub struct NoopEvents;
+impl LedgerEvents for NoopEvents {
+    #[inline(always)]
+    fn record(&mut self, _: LedgerEvent) {}
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// Writes the rejections and the changes to locked accounts to stderr, one per line.
+///
+/// Outputting to stderr slows down the program considerably.
+pub struct StderrEvents;
+impl LedgerEvents for StderrEvents {
+    fn record(&mut self, event: LedgerEvent) {
+        if event.is_notable() {
+            eprintln!("{}", event)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Writes every event to a file, one per line.
+///
+/// Clones write to the same file.
+pub struct FileEvents {
+    inner: Arc<Mutex<FileEventsInner>>,
+}
+#[derive(Debug)]
+struct FileEventsInner {
+    wri: BufWriter<File>,
+    error: Option<io::Error>,
+}
+impl FileEvents {
+    /// Creates or truncates the file at `path` and returns a sink that writes to it.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            inner: Arc::new(Mutex::new(FileEventsInner {
+                wri: BufWriter::new(File::create(path)?),
+                error: None,
+            })),
+        })
+    }
+}
+impl LedgerEvents for FileEvents {
+    fn record(&mut self, event: LedgerEvent) {
+        let mut inner = self.inner.lock().expect("poisoned event file");
+        if inner.error.is_none() {
+            if let Err(err) = writeln!(inner.wri, "{}", event) {
+                inner.error = Some(err)
+            }
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        let mut inner = self.inner.lock().expect("poisoned event file");
+        match inner.error.take() {
+            Some(err) => Err(err),
+            None => inner.wri.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+#[derive(Debug, Clone, Default)]
+/// Collects every event in memory, useful for tests.
+pub struct MemoryEvents {
+    pub events: Vec<LedgerEvent>,
+}
+#[cfg(test)]
+impl LedgerEvents for MemoryEvents {
+    fn record(&mut self, event: LedgerEvent) {
+        self.events.push(event)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+/// Selects the [LedgerEvents] sink used by [process](crate::transaction_processor::process).
+pub enum EventTarget {
+    #[default]
+    None,
+    Stderr,
+    File(PathBuf),
+}
+impl FromStr for EventTarget {
+    type Err = std::convert::Infallible;
+    /// "none" and "stderr" select those targets, anything else is a path to a file.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "none" => EventTarget::None,
+            "stderr" => EventTarget::Stderr,
+            path => EventTarget::File(path.into()),
+        })
+    }
+}
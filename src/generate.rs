@@ -1,16 +1,19 @@
 use futures::AsyncWriteExt;
 use rand::Rng;
-use smol::{fs::File, io::BufWriter};
 use std::error::Error;
 
-use crate::io::{ClientId, Transaction, TransactionId};
+use crate::{
+    amount::{Amount, SCALE},
+    compression,
+    io::{ClientId, Transaction, TransactionId},
+};
 
 const LINES: usize = 10000000;
 
 pub async fn generate(file_out: &str) -> Result<(), Box<dyn Error>> {
-    let mut wri = BufWriter::new(File::create(file_out).await?);
-    wri.write(Transaction::header().as_bytes()).await?;
-    wri.write(b"\n").await?;
+    let mut wri = compression::create(file_out).await?;
+    wri.write_all(Transaction::header().as_bytes()).await?;
+    wri.write_all(b"\n").await?;
 
     let mut rng = rand::thread_rng();
     let mut tx_id = 0u32;
@@ -38,7 +41,7 @@ pub async fn generate(file_out: &str) -> Result<(), Box<dyn Error>> {
                 Transaction::Deposit {
                     client,
                     tx,
-                    amount: rng.gen_range(0.0..1000.0),
+                    amount: Amount::from_raw(rng.gen_range(1..1000 * SCALE)),
                 }
             }
             26..=50 => {
@@ -50,7 +53,7 @@ pub async fn generate(file_out: &str) -> Result<(), Box<dyn Error>> {
                 Transaction::Withdrawal {
                     client,
                     tx,
-                    amount: rng.gen_range(0.0..1000.0),
+                    amount: Amount::from_raw(rng.gen_range(1..1000 * SCALE)),
                 }
             }
             51..=70 => {
@@ -59,7 +62,11 @@ pub async fn generate(file_out: &str) -> Result<(), Box<dyn Error>> {
                 }
                 let (tx, client) = deposits[rng.gen_range(0..deposits.len())];
 
-                Transaction::Dispute { client, tx }
+                Transaction::Dispute {
+                    client,
+                    tx,
+                    amount: None,
+                }
             }
             71..=98 => {
                 if deposits.is_empty() {
@@ -67,7 +74,11 @@ pub async fn generate(file_out: &str) -> Result<(), Box<dyn Error>> {
                 }
                 let (tx, client) = deposits[rng.gen_range(0..deposits.len())];
 
-                Transaction::Resolve { client, tx }
+                Transaction::Resolve {
+                    client,
+                    tx,
+                    amount: None,
+                }
             }
             // Low probability because with enough transactions, most users were ending up in the locked state.
             // which makes sense.
@@ -77,15 +88,20 @@ pub async fn generate(file_out: &str) -> Result<(), Box<dyn Error>> {
                 }
                 let (tx, client) = deposits[rng.gen_range(0..deposits.len())];
 
-                Transaction::ChargeBack { client, tx }
+                Transaction::ChargeBack {
+                    client,
+                    tx,
+                    amount: None,
+                }
             }
             _ => {
                 unreachable!()
             }
         };
-        wri.write(transaction.to_csv().as_bytes()).await?;
-        wri.write(b"\n").await?;
+        wri.write_all(transaction.to_csv().as_bytes()).await?;
+        wri.write_all(b"\n").await?;
     }
+    wri.close().await?;
 
     Ok(())
 }
@@ -1,17 +1,20 @@
 use rand::Rng;
 
 use futures::AsyncWriteExt;
-use smol::{fs::File, io::BufWriter};
 use std::error::Error;
 
-use crate::io::{ClientId, Transaction, TransactionId};
+use crate::{
+    amount::{Amount, SCALE},
+    compression,
+    io::{ClientId, Transaction, TransactionId},
+};
 
 const LINES: usize = 10000000;
 
 pub async fn generate_random(file_out: &str) -> Result<(), Box<dyn Error>> {
-    let mut wri = BufWriter::new(File::create(file_out).await?);
-    wri.write(Transaction::header().as_bytes()).await?;
-    wri.write(b"\n").await?;
+    let mut wri = compression::create(file_out).await?;
+    wri.write_all(Transaction::header().as_bytes()).await?;
+    wri.write_all(b"\n").await?;
 
     let mut rng = rand::thread_rng();
 
@@ -20,30 +23,34 @@ pub async fn generate_random(file_out: &str) -> Result<(), Box<dyn Error>> {
             0 => Transaction::Deposit {
                 client: ClientId(rng.gen()),
                 tx: TransactionId(rng.gen()),
-                amount: rng.gen(),
+                amount: Amount::from_raw(rng.gen_range(1..SCALE)),
             },
             1 => Transaction::Withdrawal {
                 client: ClientId(rng.gen()),
                 tx: TransactionId(rng.gen()),
-                amount: rng.gen(),
+                amount: Amount::from_raw(rng.gen_range(1..SCALE)),
             },
             2 => Transaction::Dispute {
                 client: ClientId(rng.gen()),
                 tx: TransactionId(rng.gen()),
+                amount: None,
             },
             3 => Transaction::Resolve {
                 client: ClientId(rng.gen()),
                 tx: TransactionId(rng.gen()),
+                amount: None,
             },
             4 => Transaction::ChargeBack {
                 client: ClientId(rng.gen()),
                 tx: TransactionId(rng.gen()),
+                amount: None,
             },
             _ => unreachable!(),
         };
-        wri.write(transaction.to_csv().as_bytes()).await?;
-        wri.write(b"\n").await?;
+        wri.write_all(transaction.to_csv().as_bytes()).await?;
+        wri.write_all(b"\n").await?;
     }
+    wri.close().await?;
 
     Ok(())
 }
@@ -1,10 +1,19 @@
-use futures::{StreamExt, TryStream};
+use crate::{
+    amount::{Amount, Rounding},
+    client_state::ClientStats,
+};
+use futures::{ready, stream, TryStream};
 use parse_display::{Display, FromStr};
-use serde::Serialize;
-use smol::io::AsyncBufReadExt;
+use rustc_hash::FxHashMap;
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+use smol::io::AsyncBufRead;
 use std::{
-    io::{Error, ErrorKind},
+    borrow::Cow,
+    io::Error,
+    pin::Pin,
     str::FromStr,
+    sync::Arc,
+    task::{Context, Poll},
 };
 
 #[derive(Serialize, Display, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -25,39 +34,129 @@ enum TransactionType {
     Dispute,
     Resolve,
     ChargeBack,
+    Freeze,
+    Unlock,
+}
+
+/// Longest type name, or alias, that can be recognized.
+const MAX_NAME_LEN: usize = 32;
+
+/// Lowercases `text` and strips `_` and `-` from it, so `Charge_Back` and `chargeBack` become `chargeback`.
+///
+/// Returns [None] if the result doesn't fit in `buf`.
+fn normalize_name<'a>(text: &[u8], buf: &'a mut [u8; MAX_NAME_LEN]) -> Option<&'a [u8]> {
+    let mut len = 0;
+    for &b in text.iter().filter(|&&b| b != b'_' && b != b'-') {
+        *buf.get_mut(len)? = b.to_ascii_lowercase();
+        len += 1;
+    }
+    Some(&buf[..len])
+}
+
+#[derive(Debug, Clone, Default)]
+/// Additional names accepted for the [TransactionTypes](Transaction) in the type column.
+///
+/// Parsed from a list of `alias=type` pairs separated by commas or line breaks,
+/// e.g. `credit=deposit,debit=withdrawal,cb=chargeback`.
+/// Aliases and types are matched ignoring case, `_` and `-`, like the type names.
+pub struct Aliases {
+    names: Arc<FxHashMap<Vec<u8>, TransactionType>>,
+}
+impl Aliases {
+    fn get(&self, name: &[u8]) -> Option<TransactionType> {
+        self.names.get(name).copied()
+    }
+}
+impl FromStr for Aliases {
+    type Err = InvalidAlias;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut names = FxHashMap::default();
+        for pair in s.split([',', '\n']).map(str::trim) {
+            if pair.is_empty() {
+                continue;
+            }
+            let (alias, ty) = pair
+                .split_once('=')
+                .ok_or_else(|| InvalidAlias::Syntax(pair.to_string()))?;
+            let mut buf = [0; MAX_NAME_LEN];
+            let ty = normalize_name(ty.trim().as_bytes(), &mut buf)
+                .and_then(|ty| std::str::from_utf8(ty).ok()?.parse().ok())
+                .ok_or_else(|| InvalidAlias::UnknownType(ty.trim().to_string()))?;
+            let alias = normalize_name(alias.trim().as_bytes(), &mut buf)
+                .filter(|alias| !alias.is_empty())
+                .ok_or_else(|| InvalidAlias::Syntax(pair.to_string()))?;
+            if parse_type(alias, &Aliases::default()).is_some() {
+                return Err(InvalidAlias::Reserved(pair.to_string()));
+            }
+            names.insert(alias.to_vec(), ty);
+        }
+        Ok(Aliases {
+            names: Arc::new(names),
+        })
+    }
+}
+
+#[derive(Display, Debug, Clone, PartialEq, Eq)]
+/// The reasons why an [Aliases] list can't be parsed.
+pub enum InvalidAlias {
+    #[display("'{0}' is not an alias=type pair")]
+    Syntax(String),
+    #[display("'{0}' is not a transaction type")]
+    UnknownType(String),
+    /// The alias is already the name of a type.
+    #[display("'{0}' redefines a transaction type")]
+    Reserved(String),
 }
 
 /// Represents an input transaction line in the input csv.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Transaction {
     Deposit {
         client: ClientId,
         tx: TransactionId,
-        amount: f64,
+        amount: Amount,
     },
     Withdrawal {
         client: ClientId,
         tx: TransactionId,
-        amount: f64,
+        amount: Amount,
     },
+    /// `amount` is the part of the transaction being disputed, all of it if [None].
     Dispute {
         client: ClientId,
         tx: TransactionId,
+        amount: Option<Amount>,
     },
+    /// `amount` is the part of the dispute being resolved, all of it if [None].
     Resolve {
         client: ClientId,
         tx: TransactionId,
+        amount: Option<Amount>,
     },
+    /// `amount` is the part of the dispute being charged back, all of it if [None].
     ChargeBack {
         client: ClientId,
         tx: TransactionId,
+        amount: Option<Amount>,
+    },
+    /// Administrative lock of the account, `audit` records who issued it and why.
+    Freeze {
+        client: ClientId,
+        tx: TransactionId,
+        audit: Option<String>,
+    },
+    /// Administrative unlock of the account, `audit` records who issued it and why.
+    Unlock {
+        client: ClientId,
+        tx: TransactionId,
+        audit: Option<String>,
     },
 }
 
 impl Transaction {
     /// Returns the header for serializing transactions to csv.
     pub fn header() -> &'static str {
-        "type, client, tx, amount"
+        "type, client, tx, amount, audit"
     }
 
     /// Returns a csv line representing this transaction.
@@ -69,10 +168,20 @@ impl Transaction {
             Transaction::Withdrawal { client, tx, amount } => {
                 (TransactionType::Withdrawal, client, tx, Some(amount))
             }
-            Transaction::Dispute { client, tx } => (TransactionType::Dispute, client, tx, None),
-            Transaction::Resolve { client, tx } => (TransactionType::Resolve, client, tx, None),
-            Transaction::ChargeBack { client, tx } => {
-                (TransactionType::ChargeBack, client, tx, None)
+            Transaction::Dispute { client, tx, amount } => {
+                (TransactionType::Dispute, client, tx, amount.as_ref())
+            }
+            Transaction::Resolve { client, tx, amount } => {
+                (TransactionType::Resolve, client, tx, amount.as_ref())
+            }
+            Transaction::ChargeBack { client, tx, amount } => {
+                (TransactionType::ChargeBack, client, tx, amount.as_ref())
+            }
+            Transaction::Freeze { client, tx, audit } => {
+                return admin_csv(TransactionType::Freeze, *client, *tx, audit)
+            }
+            Transaction::Unlock { client, tx, audit } => {
+                return admin_csv(TransactionType::Unlock, *client, *tx, audit)
             }
         };
         let amount = amount
@@ -81,6 +190,20 @@ impl Transaction {
         format!("{},{},{},{}", ty, client, tx, amount)
     }
 
+    /// Returns the [TransactionId] of this transaction.
+    pub fn id(&self) -> TransactionId {
+        use Transaction::*;
+        match self {
+            Deposit { tx, .. }
+            | Withdrawal { tx, .. }
+            | Dispute { tx, .. }
+            | Resolve { tx, .. }
+            | ChargeBack { tx, .. }
+            | Freeze { tx, .. }
+            | Unlock { tx, .. } => *tx,
+        }
+    }
+
     /// Returns the [ClientId] of this transaction.
     pub fn client(&self) -> ClientId {
         use Transaction::*;
@@ -89,85 +212,913 @@ impl Transaction {
             | Withdrawal { client, .. }
             | Dispute { client, .. }
             | Resolve { client, .. }
-            | ChargeBack { client, .. } => *client,
+            | ChargeBack { client, .. }
+            | Freeze { client, .. }
+            | Unlock { client, .. } => *client,
         }
     }
 }
 
-#[derive(Serialize, Debug)]
+/// Returns a csv line representing an administrative transaction, which has no amount.
+fn admin_csv(
+    ty: TransactionType,
+    client: ClientId,
+    tx: TransactionId,
+    audit: &Option<String>,
+) -> String {
+    format!(
+        "{},{},{},,{}",
+        ty,
+        client,
+        tx,
+        quote(audit.as_deref().unwrap_or(""))
+    )
+}
+
+/// Quotes `text` as described in RFC 4180 if [parse] would not read it back as is.
+fn quote(text: &str) -> Cow<'_, str> {
+    let needs_quotes = text.contains([',', '"', '\r', '\n']) || text.trim() != text;
+    if !needs_quotes {
+        return Cow::Borrowed(text);
+    }
+    Cow::Owned(format!("\"{}\"", text.replace('"', "\"\"")))
+}
+
+#[derive(Debug)]
 /// Represents an output account line in the output csv.
 pub struct Output {
     pub client: ClientId,
-    pub available: f64,
-    pub held: f64,
-    pub total: f64,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
     pub locked: bool,
+    /// Only written as [ExtendedOutput].
+    pub stats: ClientStats,
 }
 
-/// Returns a [TryStream] of [Transaction] from a byte buffer that contains a csv file.
+impl Serialize for Output {
+    /// Serializes every amount with exactly four decimal places and never in scientific notation,
+    /// so the same balances always produce byte for byte the same output.
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut output = serializer.serialize_struct("Output", 5)?;
+        output.serialize_field("client", &self.client)?;
+        output.serialize_field("available", &FixedAmount(self.available))?;
+        output.serialize_field("held", &FixedAmount(self.held))?;
+        output.serialize_field("total", &FixedAmount(self.total))?;
+        output.serialize_field("locked", &self.locked)?;
+        output.end()
+    }
+}
+
+/// Serializes an [Output] with the columns of [ClientStats] after the usual ones.
+pub struct ExtendedOutput<'a>(pub &'a Output);
+impl Serialize for ExtendedOutput<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let Output {
+            client,
+            available,
+            held,
+            total,
+            locked,
+            stats,
+        } = self.0;
+        let mut output = serializer.serialize_struct("ExtendedOutput", 12)?;
+        output.serialize_field("client", client)?;
+        output.serialize_field("available", &FixedAmount(*available))?;
+        output.serialize_field("held", &FixedAmount(*held))?;
+        output.serialize_field("total", &FixedAmount(*total))?;
+        output.serialize_field("locked", locked)?;
+        output.serialize_field("deposits", &stats.deposits)?;
+        output.serialize_field("open_disputes", &stats.open_disputes)?;
+        output.serialize_field("chargebacks", &stats.chargebacks)?;
+        output.serialize_field("deposited", &FixedAmount(stats.deposited))?;
+        output.serialize_field("withdrawn", &FixedAmount(stats.withdrawn))?;
+        output.serialize_field("last_tx", &stats.last_tx)?;
+        output.serialize_field("lock_reason", &stats.lock_reason)?;
+        output.end()
+    }
+}
+
+/// Serializes an [Amount] as a string with exactly four decimal places.
+struct FixedAmount(Amount);
+impl Serialize for FixedAmount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&format_args!("{:.4}", self.0))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// Options that change how [parse] interprets its input.
+pub struct ParseOptions {
+    /// How amounts with more than four decimal places are rounded.
+    ///
+    /// Balances are kept in ten-thousandths so the output never needs to round,
+    /// this is the only place where precision can be lost.
+    pub rounding: Rounding,
+    /// Maximum number of decimal places an input amount can have, ignoring trailing zeros.
+    ///
+    /// Amounts with more decimal places are rejected instead of rounded, so it must not be more than
+    /// [DECIMALS](crate::amount::DECIMALS) or amounts could still be rounded.
+    pub max_decimals: Option<u32>,
+    /// What to do with the lines that can't be parsed.
+    pub on_error: ErrorPolicy,
+    /// Names accepted in the type column besides the names of the types.
+    pub aliases: Aliases,
+}
+
+#[derive(Display, FromStr, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[display(style = "kebab-case")]
+/// Decides what [parse] does with the lines that can't be parsed.
 ///
-/// The csv columns must follow the order dictated by [Transaction::header].
-/// Whitespace is ignored in the rows.
+/// Errors reading the input always end the stream.
+pub enum ErrorPolicy {
+    /// Return the first error.
+    #[default]
+    Abort,
+    /// Skip the line and write the error to stderr.
+    SkipAndReport,
+    /// Skip the line.
+    SkipSilently,
+    /// Skip the line and write the error to stderr until the n-th error, which is returned.
+    #[display("abort-after-{0}")]
+    AbortAfter(usize),
+}
+
+#[derive(Display, FromStr, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[display(style = "lowercase")]
+/// The formats transactions can be read from.
+pub enum InputFormat {
+    /// Comma separated values, read by [parse].
+    #[default]
+    Csv,
+    /// One JSON object per line, read by [parse_jsonl](crate::jsonl::parse_jsonl).
+    Jsonl,
+}
+impl InputFormat {
+    /// Picks the format of `path` from its extension, `.jsonl` or `.ndjson` for JSON Lines,
+    /// ignoring a `.gz` or `.zst` compression extension.
+    pub fn from_path(path: &str) -> Self {
+        let path = path.trim_end_matches(".gz").trim_end_matches(".zst");
+        if path.ends_with(".jsonl") || path.ends_with(".ndjson") {
+            InputFormat::Jsonl
+        } else {
+            InputFormat::Csv
+        }
+    }
+}
+
+#[derive(Display, Debug, Clone, Copy, PartialEq, Eq)]
+/// The reasons why [parse] rejects an amount.
+pub enum InvalidAmount {
+    /// The amount is required but the column is empty or missing.
+    #[display("missing")]
+    Missing,
+    /// Not a plain decimal number, or too big for an [Amount].
+    #[display("not a decimal number")]
+    NotANumber,
+    /// `nan`, `inf` or `infinity`, in any case and with any sign.
+    #[display("not a finite number")]
+    NotFinite,
+    #[display("negative")]
+    Negative,
+    #[display("zero")]
+    Zero,
+    /// More decimal places than [ParseOptions::max_decimals].
+    #[display("more than {0} decimal places")]
+    TooPrecise(u32),
+}
+
+#[derive(Display, Debug)]
+/// The errors returned by [parse].
+///
+/// `line` is the number of the line in the input, starting from 1 for the header,
+/// `column` is the byte offset from the start of the line where `text` begins.
+/// For records that span several lines both refer to the line where the record starts.
+/// Missing columns are reported at the end of the line with an empty `text`.
+pub enum ParseError {
+    #[display("Missing or invalid type '{text}' in line {line} at byte {column}")]
+    Type {
+        line: usize,
+        column: usize,
+        text: String,
+    },
+    #[display("Missing or invalid client '{text}' in line {line} at byte {column}")]
+    Client {
+        line: usize,
+        column: usize,
+        text: String,
+    },
+    #[display("Missing or invalid tx '{text}' in line {line} at byte {column}")]
+    Tx {
+        line: usize,
+        column: usize,
+        text: String,
+    },
+    #[display("Invalid amount '{text}' in line {line} at byte {column}: {reason}")]
+    Amount {
+        line: usize,
+        column: usize,
+        text: String,
+        reason: InvalidAmount,
+    },
+    #[display("Unexpected extra columns '{text}' in line {line} at byte {column}")]
+    ExtraColumns {
+        line: usize,
+        column: usize,
+        text: String,
+    },
+    /// A required column is not named in the header, `text` is its name.
+    #[display("Missing required column '{text}' in the header in line {line}")]
+    MissingColumn {
+        line: usize,
+        column: usize,
+        text: String,
+    },
+    #[display("Duplicate column '{text}' in the header in line {line} at byte {column}")]
+    DuplicateColumn {
+        line: usize,
+        column: usize,
+        text: String,
+    },
+    /// A quoted field is not terminated or is followed by something other than whitespace.
+    #[display("Malformed quoted field '{text}' in line {line} at byte {column}")]
+    Syntax {
+        line: usize,
+        column: usize,
+        text: String,
+    },
+    /// `text` holds the invalid bytes, escaped.
+    #[display("Invalid UTF-8 '{text}' in line {line} at byte {column}")]
+    Encoding {
+        line: usize,
+        column: usize,
+        text: String,
+    },
+    /// A binary log is damaged or has an unsupported version.
+    ///
+    /// `column` is the byte offset from the start of the file and `line` the last line decoded before it.
+    #[display("Corrupt binary log at byte {column} after line {line}: {text}")]
+    Corrupt {
+        line: usize,
+        column: usize,
+        text: String,
+    },
+    /// The input could not be read.
+    #[display("{0}")]
+    Io(Error),
+}
+
+impl ParseError {
+    /// Returns true if the error makes the rest of the input unreadable.
+    pub fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            ParseError::Io(_)
+                | ParseError::Corrupt { .. }
+                | ParseError::MissingColumn { .. }
+                | ParseError::DuplicateColumn { .. }
+        )
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ParseError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<Error> for ParseError {
+    fn from(err: Error) -> Self {
+        ParseError::Io(err)
+    }
+}
+
+/// Returns a [TryStream] of [Transaction] from a byte buffer that contains a csv file,
+/// each one paired with the number of the line it was found in, starting from 1.
+///
+/// Columns are mapped by the names in the header, ignoring case, whitespace and unknown columns.
+/// The first record is only a header if it names a known column,
+/// files without one must follow the order dictated by [Transaction::header].
+/// Fields can be quoted as described in RFC 4180 and whitespace around them is ignored.
+///
+/// Records are parsed as bytes straight from the buffer of `read`, only the type and audit
+/// columns have to be valid UTF-8.
+///
+/// Lines that can't be parsed are handled according to [ParseOptions::on_error],
+/// errors in the header and reading the input always end the stream.
+// The multithreaded mode parses with chunked::parse_chunked instead.
+#[cfg_attr(feature = "multithreaded", allow(dead_code))]
 pub fn parse(
-    read: impl AsyncBufReadExt + Unpin,
-) -> impl TryStream<Ok = Transaction, Error = Error> {
-    let lines = read.lines().enumerate().skip(1);
-
-    lines.map(move |(i, line)| {
-        line.and_then(|line| {
-            let mut elems = line.split(',').take(4).map(|e| e.trim());
-
-            let ty = elems
-                .next()
-                .and_then(|e| FromStr::from_str(e).ok())
-                .ok_or_else(field_error("type", i))?;
-
-            let client = elems
-                .next()
-                .and_then(|e| atoi::atoi(e.as_bytes()))
-                .map(ClientId)
-                .ok_or_else(field_error("client", i))?;
-
-            let tx = elems
-                .next()
-                .and_then(|e| atoi::atoi(e.as_bytes()))
-                .map(TransactionId)
-                .ok_or_else(field_error("tx", i))?;
-
-            let mut get_amount = move || {
-                elems
-                    .next()
-                    .and_then(|e| fast_float::parse::<f64, _>(e).ok())
-                    .ok_or_else(field_error("amount", i))
+    read: impl AsyncBufRead + Unpin,
+    options: ParseOptions,
+) -> impl TryStream<Ok = (usize, Transaction), Error = ParseError> {
+    let mut layout = None;
+    let on_error = options.on_error;
+    parse_records(
+        read,
+        RecordScanner::default(),
+        on_error,
+        move |i, record| parse_next(&mut layout, i, record, &options),
+    )
+}
+
+/// Returns a stream of the transactions returned by `f` for each record of `read`, found by `scanner`.
+///
+/// `f` gets the line where the record starts and its bytes, and returns nothing for records to skip.
+/// Errors are handled according to `on_error`.
+pub fn parse_records(
+    read: impl AsyncBufRead + Unpin,
+    scanner: RecordScanner,
+    on_error: ErrorPolicy,
+    mut f: impl FnMut(usize, &[u8]) -> Result<Option<(usize, Transaction)>, ParseError>,
+) -> impl TryStream<Ok = (usize, Transaction), Error = ParseError> {
+    let mut records = Records::new(read, scanner);
+    let mut errors = ErrorFilter::new(on_error);
+    stream::poll_fn(move |cx| loop {
+        let transaction = match ready!(records.poll_record(cx, &mut f)) {
+            Some(Ok(transaction)) => transaction.transpose(),
+            Some(Err(err)) => Some(Err(ParseError::Io(err))),
+            None => return Poll::Ready(None),
+        };
+        if let Some(transaction) = transaction.and_then(|transaction| errors.filter(transaction)) {
+            return Poll::Ready(Some(transaction));
+        }
+    })
+}
+
+/// Applies an [ErrorPolicy] to a sequence of parsing results.
+pub struct ErrorFilter {
+    policy: ErrorPolicy,
+    errors: usize,
+}
+impl ErrorFilter {
+    pub fn new(policy: ErrorPolicy) -> Self {
+        ErrorFilter { policy, errors: 0 }
+    }
+    /// Returns `result` unless it is an error that the policy skips.
+    pub fn filter<T>(&mut self, result: Result<T, ParseError>) -> Option<Result<T, ParseError>> {
+        let err = match result {
+            Err(err) if !err.is_fatal() => err,
+            result => return Some(result),
+        };
+        self.errors += 1;
+        match self.policy {
+            ErrorPolicy::Abort => Some(Err(err)),
+            ErrorPolicy::AbortAfter(n) if self.errors >= n => Some(Err(err)),
+            ErrorPolicy::SkipAndReport | ErrorPolicy::AbortAfter(_) => {
+                eprintln!("Skipping line: {}", err);
+                None
+            }
+            ErrorPolicy::SkipSilently => None,
+        }
+    }
+}
+
+/// Parses `record`, which starts in line `i`.
+///
+/// The first record is used to find the [Layout] of the input, nothing is returned if it is a header.
+fn parse_next(
+    layout: &mut Option<Layout>,
+    i: usize,
+    record: &[u8],
+    options: &ParseOptions,
+) -> Result<Option<(usize, Transaction)>, ParseError> {
+    if layout.is_none() {
+        match Layout::from_header(record, i, &options.aliases)? {
+            Some(header) => {
+                *layout = Some(header);
+                return Ok(None);
+            }
+            None => *layout = Some(Layout::positional()),
+        }
+    }
+    let layout = layout
+        .as_ref()
+        .expect("the layout is set by the first record");
+    Ok(Some((i, parse_record(record, i, layout, options)?)))
+}
+
+#[derive(Display, FromStr, Debug, Clone, Copy, PartialEq, Eq)]
+#[display(style = "lowercase")]
+/// The columns that [parse] understands.
+pub enum Column {
+    Type,
+    Client,
+    Tx,
+    Amount,
+    Audit,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+/// The [Column] found in each position of the input, [None] for unknown columns.
+pub struct Layout {
+    columns: Vec<Option<Column>>,
+}
+impl Layout {
+    /// Returns the layout of files without a header, which follows [Transaction::header].
+    pub fn positional() -> Self {
+        use Column::*;
+        Layout {
+            columns: vec![
+                Some(Type),
+                Some(Client),
+                Some(Tx),
+                Some(Amount),
+                Some(Audit),
+            ],
+        }
+    }
+
+    /// Reads the layout from `record`, the first record of the input, in line `i`.
+    ///
+    /// Returns [None] if `record` is not a header but a transaction, because its type is known,
+    /// possibly as one of `aliases`, or it doesn't name any known column.
+    /// Those records are parsed by position like the rest of the input, so their errors are reported.
+    pub fn from_header(
+        record: &[u8],
+        i: usize,
+        aliases: &Aliases,
+    ) -> Result<Option<Layout>, ParseError> {
+        let mut names = Vec::new();
+        for field in Fields::new(record) {
+            names.push(field.map_err(syntax_error(record, i))?);
+        }
+        if let Some(first) = names.first() {
+            if parse_type(&first.text, aliases).is_some() {
+                return Ok(None);
+            }
+        }
+
+        let mut columns = Vec::with_capacity(names.len());
+        for field in &names {
+            let name: String = String::from_utf8_lossy(&field.text)
+                .chars()
+                .filter(|c| !c.is_whitespace())
+                .flat_map(char::to_lowercase)
+                .collect();
+            let column = name.parse::<Column>().ok();
+            if column.is_some() && columns.contains(&column) {
+                return Err(ParseError::DuplicateColumn {
+                    line: i,
+                    column: field.column,
+                    text: lossy(&field.text),
+                });
+            }
+            columns.push(column);
+        }
+
+        if columns.iter().all(Option::is_none) {
+            return Ok(None);
+        }
+        for required in [Column::Type, Column::Client, Column::Tx].iter() {
+            if !columns.contains(&Some(*required)) {
+                return Err(ParseError::MissingColumn {
+                    line: i,
+                    column: record.len(),
+                    text: required.to_string(),
+                });
+            }
+        }
+        Ok(Some(Layout { columns }))
+    }
+}
+
+/// Finds the csv records in the buffer of `read`, each one with the number of the line it starts in.
+///
+/// A record spans several lines when a quoted field contains line breaks, they are kept in the record.
+/// Records are handed out straight from the buffer, only the ones that straddle two fills of it are copied.
+struct Records<R> {
+    read: R,
+    /// Start of a record that continues after the end of the buffer.
+    partial: Vec<u8>,
+    scanner: RecordScanner,
+    /// Line where the current record starts.
+    line: usize,
+    done: bool,
+}
+impl<R: AsyncBufRead + Unpin> Records<R> {
+    fn new(read: R, scanner: RecordScanner) -> Self {
+        Records {
+            read,
+            partial: Vec::new(),
+            scanner,
+            line: 1,
+            done: false,
+        }
+    }
+
+    /// Polls for the next record and returns the result of calling `f` with its line and bytes.
+    fn poll_record<T>(
+        &mut self,
+        cx: &mut Context<'_>,
+        f: impl FnOnce(usize, &[u8]) -> T,
+    ) -> Poll<Option<Result<T, Error>>> {
+        loop {
+            if self.done {
+                return Poll::Ready(None);
+            }
+            let buf = match ready!(Pin::new(&mut self.read).poll_fill_buf(cx)) {
+                Ok(buf) => buf,
+                Err(err) => {
+                    self.done = true;
+                    return Poll::Ready(Some(Err(err)));
+                }
             };
+            if buf.is_empty() {
+                // The last record doesn't end in a line break.
+                self.done = true;
+                if self.partial.is_empty() {
+                    return Poll::Ready(None);
+                }
+                return Poll::Ready(Some(Ok(f(self.line, &self.partial))));
+            }
 
-            let transaction = match ty {
-                TransactionType::Deposit => Transaction::Deposit {
-                    client,
-                    tx,
-                    amount: get_amount()?,
-                },
-                TransactionType::Withdrawal => Transaction::Withdrawal {
-                    client,
-                    tx,
-                    amount: get_amount()?,
-                },
-                TransactionType::Dispute => Transaction::Dispute { client, tx },
-                TransactionType::Resolve => Transaction::Resolve { client, tx },
-                TransactionType::ChargeBack => Transaction::ChargeBack { client, tx },
+            let Some(end) = self.scanner.find_end(buf) else {
+                self.partial.extend_from_slice(buf);
+                let len = buf.len();
+                Pin::new(&mut self.read).consume(len);
+                continue;
             };
-            Ok(transaction)
+
+            let line = self.line;
+            self.line += self.scanner.finish();
+            let record = if self.partial.is_empty() {
+                f(line, &buf[..end])
+            } else {
+                self.partial.extend_from_slice(&buf[..end]);
+                let record = f(line, &self.partial);
+                self.partial.clear();
+                record
+            };
+            Pin::new(&mut self.read).consume(end + 1);
+            return Poll::Ready(Some(Ok(record)));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// Finds where csv records end, which is the first line break that is not inside a quoted field.
+///
+/// Keeps track of the fields seen so far, so a record can be scanned in several pieces.
+/// A quote only starts a quoted field when it is the first byte of the field that isn't
+/// whitespace, like in [Fields], so stray quotes in unquoted fields don't hide line breaks.
+pub struct RecordScanner {
+    /// Where the scanned part of the current record ends.
+    state: ScanState,
+    /// Line breaks inside quotes seen in the current record.
+    breaks: usize,
+    /// Every line is a record, quotes don't matter.
+    lines: bool,
+}
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum ScanState {
+    /// Only whitespace since the start of the field.
+    #[default]
+    FieldStart,
+    /// In a field that doesn't start with a quote.
+    Unquoted,
+    /// In a quoted field.
+    Quoted,
+    /// After a quote in a quoted field, which either closes it or escapes the next quote.
+    QuoteInQuoted,
+}
+impl RecordScanner {
+    /// Returns a scanner where every line is a record, for formats that can't have line breaks
+    /// inside a record and where quotes mean something else, like JSON Lines.
+    pub fn lines() -> Self {
+        RecordScanner {
+            lines: true,
+            ..Default::default()
+        }
+    }
+    /// Returns the position of the line break that ends the current record in `bytes`,
+    /// which continue the part of the record scanned so far.
+    pub fn find_end(&mut self, bytes: &[u8]) -> Option<usize> {
+        if self.lines {
+            return find(bytes, b'\n');
+        }
+        for (position, &byte) in bytes.iter().enumerate() {
+            self.state = match (self.state, byte) {
+                (ScanState::Quoted, b'\n') => {
+                    self.breaks += 1;
+                    ScanState::Quoted
+                }
+                (ScanState::Quoted, b'"') => ScanState::QuoteInQuoted,
+                (ScanState::Quoted, _) => ScanState::Quoted,
+                (ScanState::QuoteInQuoted, b'"') => ScanState::Quoted,
+                (_, b'\n') => return Some(position),
+                (_, b',') => ScanState::FieldStart,
+                (ScanState::FieldStart, b'"') => ScanState::Quoted,
+                (ScanState::FieldStart, byte) if byte.is_ascii_whitespace() => {
+                    ScanState::FieldStart
+                }
+                // Anything after the closing quote is a syntax error for the parser to report.
+                _ => ScanState::Unquoted,
+            };
+        }
+        None
+    }
+    /// Starts scanning a new record, returns the number of lines the current one spans.
+    pub fn finish(&mut self) -> usize {
+        let lines = 1 + self.breaks;
+        self.state = ScanState::FieldStart;
+        self.breaks = 0;
+        lines
+    }
+}
+
+/// A trimmed column of a csv record and the byte offset where it starts.
+///
+/// `text` only needs to be owned when a quoted field contains escaped quotes.
+pub struct Field<'a> {
+    pub column: usize,
+    pub text: Cow<'a, [u8]>,
+}
+
+/// Iterates over the [Fields](Field) of a csv record.
+///
+/// Unquoted fields are split on commas and trimmed without copying, which is the fast path.
+/// Quoted fields keep everything between the quotes, `""` is an escaped quote.
+///
+/// Returns the byte offset of the problem if a quoted field is not terminated
+/// or is followed by something other than whitespace, and stops there.
+struct Fields<'a> {
+    record: &'a [u8],
+    position: Option<usize>,
+}
+impl<'a> Fields<'a> {
+    fn new(record: &'a [u8]) -> Self {
+        Fields {
+            record,
+            position: Some(0),
+        }
+    }
+}
+impl<'a> Iterator for Fields<'a> {
+    type Item = Result<Field<'a>, usize>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let record = self.record;
+        let start = self.position?;
+        let rest = &record[start..];
+        let column = start + rest.len() - rest.trim_ascii_start().len();
+
+        if !rest.trim_ascii_start().starts_with(b"\"") {
+            let end = find(rest, b',').map(|end| start + end);
+            self.position = end.map(|end| end + 1);
+            let raw = &record[start..end.unwrap_or(record.len())];
+            return Some(Ok(Field {
+                column,
+                text: Cow::Borrowed(raw.trim_ascii()),
+            }));
+        }
+
+        // Quoted field, only copied if it contains escaped quotes.
+        let mut owned: Option<Vec<u8>> = None;
+        let mut from = column + 1;
+        let (text, after) = loop {
+            let Some(quote) = find(&record[from..], b'"').map(|quote| from + quote) else {
+                self.position = None;
+                return Some(Err(column));
+            };
+            if record[quote + 1..].starts_with(b"\"") {
+                owned
+                    .get_or_insert_with(Vec::new)
+                    .extend_from_slice(&record[from..=quote]);
+                from = quote + 2;
+                continue;
+            }
+            let text = match owned.take() {
+                Some(mut text) => {
+                    text.extend_from_slice(&record[from..quote]);
+                    Cow::Owned(text)
+                }
+                None => Cow::Borrowed(&record[from..quote]),
+            };
+            break (text, quote + 1);
+        };
+
+        // Only whitespace can follow the closing quote.
+        let rest = &record[after..];
+        let end = find(rest, b',');
+        let between = &rest[..end.unwrap_or(rest.len())];
+        if !between.trim_ascii().is_empty() {
+            self.position = None;
+            return Some(Err(after + between.len() - between.trim_ascii_start().len()));
+        }
+        self.position = end.map(|end| after + end + 1);
+        Some(Ok(Field { column, text }))
+    }
+}
+
+/// Returns the position of the first `byte` in `bytes`.
+fn find(bytes: &[u8], byte: u8) -> Option<usize> {
+    bytes.iter().position(|&b| b == byte)
+}
+
+/// Returns `bytes` as a [String], replacing invalid UTF-8, for error messages.
+fn lossy(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+/// Parses the type named `text`, ignoring case, `_` and `-`, or one of its `aliases`.
+fn parse_type(text: &[u8], aliases: &Aliases) -> Option<TransactionType> {
+    let mut buf = [0; MAX_NAME_LEN];
+    let name = normalize_name(text, &mut buf)?;
+    let ty = std::str::from_utf8(name).ok()?.parse().ok();
+    ty.or_else(|| aliases.get(name))
+}
+
+fn syntax_error(record: &[u8], i: usize) -> impl Fn(usize) -> ParseError + '_ {
+    move |column| ParseError::Syntax {
+        line: i,
+        column,
+        text: lossy(&record[column..]),
+    }
+}
+
+/// Parses `record`, which starts in line `i` of the input and follows `layout`.
+pub fn parse_record(
+    record: &[u8],
+    i: usize,
+    layout: &Layout,
+    options: &ParseOptions,
+) -> Result<Transaction, ParseError> {
+    let mut fields: [Option<Field>; 5] = Default::default();
+    for (position, field) in Fields::new(record).enumerate() {
+        let field = field.map_err(syntax_error(record, i))?;
+        match layout.columns.get(position) {
+            Some(Some(column)) => fields[*column as usize] = Some(field),
+            Some(None) => {}
+            None => {
+                return Err(ParseError::ExtraColumns {
+                    line: i,
+                    column: field.column,
+                    text: lossy(&record[field.column..]),
+                })
+            }
+        }
+    }
+    parse_fields(fields, record.len(), i, options)
+}
+
+/// Parses the `fields` of a record, indexed by [Column], which starts in line `i`.
+///
+/// Missing fields are empty and start at byte `end`.
+pub fn parse_fields(
+    mut fields: [Option<Field>; 5],
+    end: usize,
+    i: usize,
+    options: &ParseOptions,
+) -> Result<Transaction, ParseError> {
+    let mut take = |column: Column| {
+        fields[column as usize].take().unwrap_or(Field {
+            column: end,
+            text: Cow::Borrowed(b""),
         })
+    };
+
+    let field = take(Column::Type);
+    let ty = parse_type(&field.text, &options.aliases).ok_or_else(|| ParseError::Type {
+        line: i,
+        column: field.column,
+        text: lossy(&field.text),
+    })?;
+
+    let field = take(Column::Client);
+    let client = atoi::atoi(&field.text)
+        .map(ClientId)
+        .ok_or_else(|| ParseError::Client {
+            line: i,
+            column: field.column,
+            text: lossy(&field.text),
+        })?;
+
+    let field = take(Column::Tx);
+    let tx = atoi::atoi(&field.text)
+        .map(TransactionId)
+        .ok_or_else(|| ParseError::Tx {
+            line: i,
+            column: field.column,
+            text: lossy(&field.text),
+        })?;
+
+    // The amount is optional in disputes, resolves and chargebacks.
+    let amount = take(Column::Amount);
+    let required_amount = || parse_amount(&amount, options, i);
+    let optional_amount = || {
+        Some(&amount)
+            .filter(|field| !field.text.is_empty())
+            .map(|field| parse_amount(field, options, i))
+            .transpose()
+    };
+    // Only administrative transactions have an audit column.
+    let audit = take(Column::Audit);
+    let audit = || {
+        if audit.text.is_empty() {
+            return Ok(None);
+        }
+        match std::str::from_utf8(&audit.text) {
+            Ok(text) => Ok(Some(text.to_string())),
+            Err(err) => {
+                let start = err.valid_up_to();
+                let end = start + err.error_len().unwrap_or(audit.text.len() - start);
+                Err(ParseError::Encoding {
+                    line: i,
+                    column: audit.column + start,
+                    text: audit.text[start..end].escape_ascii().to_string(),
+                })
+            }
+        }
+    };
+
+    Ok(match ty {
+        TransactionType::Deposit => Transaction::Deposit {
+            client,
+            tx,
+            amount: required_amount()?,
+        },
+        TransactionType::Withdrawal => Transaction::Withdrawal {
+            client,
+            tx,
+            amount: required_amount()?,
+        },
+        TransactionType::Dispute => Transaction::Dispute {
+            client,
+            tx,
+            amount: optional_amount()?,
+        },
+        TransactionType::Resolve => Transaction::Resolve {
+            client,
+            tx,
+            amount: optional_amount()?,
+        },
+        TransactionType::ChargeBack => Transaction::ChargeBack {
+            client,
+            tx,
+            amount: optional_amount()?,
+        },
+        TransactionType::Freeze => Transaction::Freeze {
+            client,
+            tx,
+            audit: audit()?,
+        },
+        TransactionType::Unlock => Transaction::Unlock {
+            client,
+            tx,
+            audit: audit()?,
+        },
     })
 }
 
-fn field_error(field: &'static str, i: usize) -> impl Fn() -> Error {
-    move || {
-        Error::new(
-            ErrorKind::InvalidData,
-            format!("Missing or invalid {} in line {}", field, i),
-        )
+/// Parses the amount in `field`, found in line `i`, and checks that it is valid for a transaction.
+fn parse_amount(field: &Field, options: &ParseOptions, i: usize) -> Result<Amount, ParseError> {
+    let error = |reason| ParseError::Amount {
+        line: i,
+        column: field.column,
+        text: lossy(&field.text),
+        reason,
+    };
+    let text = &*field.text;
+    if text.is_empty() {
+        return Err(error(InvalidAmount::Missing));
+    }
+    let unsigned = &text[text
+        .iter()
+        .position(|&b| b != b'-' && b != b'+')
+        .unwrap_or(text.len())..];
+    if ["nan", "inf", "infinity"]
+        .iter()
+        .any(|s| unsigned.eq_ignore_ascii_case(s.as_bytes()))
+    {
+        return Err(error(InvalidAmount::NotFinite));
     }
+
+    let amount = Amount::parse_rounded(text, options.rounding)
+        .ok_or_else(|| error(InvalidAmount::NotANumber))?;
+
+    // The sign is checked on the text, amounts that round to zero are still negative.
+    let negative = text.starts_with(b"-") && unsigned.iter().any(|b| (b'1'..=b'9').contains(b));
+    if negative {
+        return Err(error(InvalidAmount::Negative));
+    }
+    if let Some(max) = options.max_decimals {
+        let decimals = find(text, b'.').map_or(0, |dot| {
+            let frac = &text[dot + 1..];
+            frac.iter()
+                .rposition(|&b| b != b'0')
+                .map_or(0, |last| last + 1)
+        });
+        if decimals > max as usize {
+            return Err(error(InvalidAmount::TooPrecise(max)));
+        }
+    }
+    if amount == Amount::ZERO {
+        return Err(error(InvalidAmount::Zero));
+    }
+    Ok(amount)
 }
 
 #[cfg(test)]
@@ -178,226 +1129,590 @@ mod tests {
 
     use super::*;
 
+    fn amount(s: &str) -> Amount {
+        s.parse().unwrap()
+    }
+
+    /// Returns the first error of parsing `input` with `options`.
+    async fn error(input: &[u8], options: ParseOptions) -> ParseError {
+        let mut txs = parse(BufReader::new(input), options);
+        loop {
+            match txs.try_next().await {
+                Ok(Some(_)) => continue,
+                Ok(None) => panic!("no error in the input"),
+                Err(err) => return err,
+            }
+        }
+    }
+
+    /// Asserts that the first error of parsing `$input` is a `$variant` at `$line` and `$column` with `$text`.
+    macro_rules! assert_error {
+        ($input:expr, $variant:ident { $line:expr, $column:expr, $text:expr }) => {
+            match error($input.as_bytes(), ParseOptions::default()).await {
+                ParseError::$variant {
+                    line, column, text, ..
+                } => assert_eq!((line, column, text.as_str()), ($line, $column, $text)),
+                err => panic!("expected a {} error, got {:?}", stringify!($variant), err),
+            }
+        };
+    }
+
     #[smol_potat::test]
     async fn invalid_type() {
         // Invalid string
-        let br = BufReader::new(
-            r#"
-        test
-        "#
-            .as_bytes(),
-        );
+        assert_error!("        test\n", Type { 1, 8, "test" });
+        // Number
+        assert_error!("        1\n", Type { 1, 8, "1" });
+        // Empty
+        assert_error!("        ,1\n", Type { 1, 8, "" });
+        // Alias that wasn't configured
+        assert_error!("        credit, 1, 1, 1\n", Type { 1, 8, "credit" });
+    }
+
+    #[smol_potat::test]
+    async fn type_names() {
+        let options = ParseOptions {
+            aliases: "credit=deposit, Debit = WITHDRAWAL\ncb=charge_back"
+                .parse()
+                .unwrap(),
+            ..Default::default()
+        };
+        let input = "CB, 1, 1\nDeposit, 1, 1, 1\nDEPOSIT, 1, 2, 1\ncharge_back, 1, 1\n\
+                     chargeBack, 1, 1\nCharge-Back, 1, 1\ncredit, 1, 3, 1\nDE-BIT, 1, 4, 1\n";
+        let types: Vec<_> = parse(BufReader::new(input.as_bytes()), options)
+            .map_ok(|(_, tx)| tx.to_csv().split(',').next().unwrap().to_string())
+            .try_collect()
+            .await
+            .unwrap();
         assert_eq!(
-            parse(br).try_next().await.unwrap_err().to_string(),
-            field_error("type", 1)().to_string()
+            types,
+            vec![
+                "chargeback",
+                "deposit",
+                "deposit",
+                "chargeback",
+                "chargeback",
+                "chargeback",
+                "deposit",
+                "withdrawal"
+            ]
         );
 
-        // Number
-        let br = BufReader::new(
-            r#"
-        1
-        "#
-            .as_bytes(),
-        );
         assert_eq!(
-            parse(br).try_next().await.unwrap_err().to_string(),
-            field_error("type", 1)().to_string()
+            "credit".parse::<Aliases>().unwrap_err(),
+            InvalidAlias::Syntax("credit".to_string())
         );
-
-        // Empty
-        let br = BufReader::new(
-            r#"
-        ,1
-        "#
-            .as_bytes(),
+        assert_eq!(
+            "credit=money".parse::<Aliases>().unwrap_err(),
+            InvalidAlias::UnknownType("money".to_string())
         );
         assert_eq!(
-            parse(br).try_next().await.unwrap_err().to_string(),
-            field_error("type", 1)().to_string()
+            "Deposit=withdrawal".parse::<Aliases>().unwrap_err(),
+            InvalidAlias::Reserved("Deposit=withdrawal".to_string())
         );
     }
 
     #[smol_potat::test]
     async fn invalid_client() {
         // String
-        let br = BufReader::new(
-            r#"
-        deposit, foo
-        "#
-            .as_bytes(),
+        assert_error!("        deposit, foo\n", Client { 1, 17, "foo" });
+        // Negative number
+        assert_error!("        deposit, -3\n", Client { 1, 17, "-3" });
+        // Overflow
+        let input = format!("        deposit, {}\n", u16::MAX as u32 + 1);
+        assert_error!(input, Client { 1, 17, "65536" });
+        // Empty
+        assert_error!("        deposit, ,4\n", Client { 1, 17, "" });
+        // Missing
+        assert_error!("        deposit\n", Client { 1, 15, "" });
+    }
+
+    #[smol_potat::test]
+    async fn invalid_tx() {
+        // String
+        assert_error!("        deposit, 4, bar\n", Tx { 1, 20, "bar" });
+        // Negative number
+        assert_error!("        deposit,5, -5\n", Tx { 1, 19, "-5" });
+        // Overflow
+        let input = format!("        deposit,6, {}\n", u32::MAX as u64 + 1);
+        assert_error!(input, Tx { 1, 19, "4294967296" });
+        // Empty
+        assert_error!("        deposit, 4,,\n", Tx { 1, 19, "" });
+    }
+
+    #[smol_potat::test]
+    async fn invalid_amount() {
+        // String
+        assert_error!("        deposit, 2, 3 , eheh \n", Amount { 1, 24, "eheh" });
+        // Empty
+        assert_error!(
+            "        deposit, 3,4,5\n        deposit, 2, 3 , \n",
+            Amount { 2, 24, "" }
         );
+        // Missing
+        assert_error!("deposit, 2, 3\n", Amount { 1, 13, "" });
+        // Invalid optional amount
+        assert_error!("        dispute, 2, 3, lol\n", Amount { 1, 23, "lol" });
+    }
+
+    #[smol_potat::test]
+    async fn invalid_line() {
+        assert_error!("deposit, 1, 2, 3, , x, y\n", ExtraColumns { 1, 20, "x, y" });
+        assert_error!("freeze, 1, 2, , audit,\n", ExtraColumns { 1, 22, "" });
+
+        match error(b"freeze, 1, 2, , bob \xff\n", ParseOptions::default()).await {
+            ParseError::Encoding { line, column, text } => {
+                assert_eq!((line, column, text.as_str()), (1, 20, "\\xff"))
+            }
+            err => panic!("expected an Encoding error, got {:?}", err),
+        }
+    }
+
+    #[smol_potat::test]
+    async fn quoted_fields() {
+        let input = "\"type\",\"client\",\"tx\",\"amount\",\"audit\"\r\n\
+                     \"deposit\",\"1\",\"2\",\"1.5\"\r\n\
+                     withdrawal, \"1\" ,3,  \"0.5\"  \r\n\
+                     freeze,1,4,,\"alice: \"\"fraud\"\", see #42\"\r\n\
+                     unlock,1,5,\"\",\"bob: multi\r\nline note\"\r\n\
+                     dispute,1,2,\"\"\r\n";
+        let mut txs = parse(BufReader::new(input.as_bytes()), ParseOptions::default());
         assert_eq!(
-            parse(br).try_next().await.unwrap_err().to_string(),
-            field_error("client", 1)().to_string()
+            txs.try_next().await.unwrap().unwrap(),
+            (
+                2,
+                Transaction::Deposit {
+                    client: ClientId(1),
+                    tx: TransactionId(2),
+                    amount: amount("1.5"),
+                }
+            )
         );
-
-        // Negative number
-        let br = BufReader::new(
-            r#"
-        deposit, -3
-        "#
-            .as_bytes(),
+        assert_eq!(
+            txs.try_next().await.unwrap().unwrap().1,
+            Transaction::Withdrawal {
+                client: ClientId(1),
+                tx: TransactionId(3),
+                amount: amount("0.5"),
+            }
         );
+        let freeze = txs.try_next().await.unwrap().unwrap().1;
         assert_eq!(
-            parse(br).try_next().await.unwrap_err().to_string(),
-            field_error("client", 1)().to_string()
+            freeze,
+            Transaction::Freeze {
+                client: ClientId(1),
+                tx: TransactionId(4),
+                audit: Some("alice: \"fraud\", see #42".to_string()),
+            }
         );
-
-        // Overflow
-        let string = format!(
-            r#"
-        deposit, {}
-        "#,
-            std::u16::MAX as u32 + 1
+        let unlock = txs.try_next().await.unwrap().unwrap();
+        assert_eq!(
+            unlock,
+            (
+                5,
+                Transaction::Unlock {
+                    client: ClientId(1),
+                    tx: TransactionId(5),
+                    audit: Some("bob: multi\r\nline note".to_string()),
+                }
+            )
         );
-        let br = BufReader::new(string.as_bytes());
+        // The record after a multi line one keeps counting lines.
         assert_eq!(
-            parse(br).try_next().await.unwrap_err().to_string(),
-            field_error("client", 1)().to_string()
+            txs.try_next().await.unwrap().unwrap(),
+            (
+                7,
+                Transaction::Dispute {
+                    client: ClientId(1),
+                    tx: TransactionId(2),
+                    amount: None,
+                }
+            )
         );
+        assert!(txs.try_next().await.unwrap().is_none());
 
-        // Empty
-        let br = BufReader::new(
-            r#"
-        deposit, ,4
-        "#
-            .as_bytes(),
+        // Quoted audits survive a round trip through to_csv.
+        for tx in [freeze, unlock.1].iter() {
+            let input = format!("{}\n{}\n", Transaction::header(), tx.to_csv());
+            let mut txs = parse(BufReader::new(input.as_bytes()), ParseOptions::default());
+            assert_eq!(&txs.try_next().await.unwrap().unwrap().1, tx);
+        }
+    }
+
+    #[smol_potat::test]
+    async fn records_across_buffer_fills() {
+        let input = "type, client, tx, amount, audit\n\
+                     deposit, 1, 1, 1.5\r\n\
+                     freeze, 1, 2, , \"multi\nline, \"\"quoted\"\"\"\n\
+                     \n\
+                     unlock, 1, 3,, no trailing line break";
+        let parse_all = |capacity| async move {
+            let read = BufReader::with_capacity(capacity, input.as_bytes());
+            let options = ParseOptions {
+                on_error: ErrorPolicy::SkipSilently,
+                ..Default::default()
+            };
+            parse(read, options).try_collect::<Vec<_>>().await.unwrap()
+        };
+        let expected = parse_all(1024).await;
+        assert_eq!(expected.len(), 3);
+        assert_eq!(expected[2].0, 6);
+        for capacity in 1..16 {
+            assert_eq!(parse_all(capacity).await, expected, "capacity {}", capacity);
+        }
+    }
+
+    #[smol_potat::test]
+    async fn quotes_inside_unquoted_fields() {
+        // Only a quote at the start of a field opens a quoted field, this one is part of the audit.
+        let input = "freeze,1,2,,O\"Brien\ndeposit,2,3,5\ndeposit,2,4,5\nunlock,1,5,,x\"y\n";
+        for capacity in [1, 3, 1024] {
+            let read = BufReader::with_capacity(capacity, input.as_bytes());
+            let txs: Vec<_> = parse(read, ParseOptions::default())
+                .try_collect()
+                .await
+                .unwrap();
+            assert_eq!(
+                txs,
+                vec![
+                    (
+                        1,
+                        Transaction::Freeze {
+                            client: ClientId(1),
+                            tx: TransactionId(2),
+                            audit: Some("O\"Brien".to_string()),
+                        }
+                    ),
+                    (
+                        2,
+                        Transaction::Deposit {
+                            client: ClientId(2),
+                            tx: TransactionId(3),
+                            amount: amount("5"),
+                        }
+                    ),
+                    (
+                        3,
+                        Transaction::Deposit {
+                            client: ClientId(2),
+                            tx: TransactionId(4),
+                            amount: amount("5"),
+                        }
+                    ),
+                    (
+                        4,
+                        Transaction::Unlock {
+                            client: ClientId(1),
+                            tx: TransactionId(5),
+                            audit: Some("x\"y".to_string()),
+                        }
+                    ),
+                ],
+                "capacity {}",
+                capacity
+            );
+        }
+    }
+
+    #[smol_potat::test]
+    async fn malformed_quotes() {
+        assert_error!("deposit, \"1\"2, 3, 4\n", Syntax { 1, 12, "2, 3, 4" });
+        assert_error!("deposit, 1, 3, \"4\ndeposit, 1, 4, 5\n", Syntax { 1, 15, "\"4\ndeposit, 1, 4, 5\n" });
+    }
+
+    #[smol_potat::test]
+    async fn header_mapping() {
+        let transactions = |input: &'static str| async move {
+            parse(BufReader::new(input.as_bytes()), ParseOptions::default())
+                .try_collect::<Vec<_>>()
+                .await
+        };
+        let deposit = |tx, amount_: &str| Transaction::Deposit {
+            client: ClientId(1),
+            tx: TransactionId(tx),
+            amount: amount(amount_),
+        };
+
+        // Reordered, differently cased and with unknown columns.
+        assert_eq!(
+            transactions(" Client ,TYPE,Amount, source ,t x\n1, deposit, 2.5, web, 7\n")
+                .await
+                .unwrap(),
+            vec![(2, deposit(7, "2.5"))]
         );
+        // Rows can't have more columns than the header.
+        match transactions("client,type,tx,amount\n1,deposit,7,2,3\n").await {
+            Err(ParseError::ExtraColumns {
+                line: 2,
+                column: 14,
+                ..
+            }) => {}
+            res => panic!("expected an ExtraColumns error, got {:?}", res),
+        }
+        // Headerless files are read by position, starting from line 1.
+        assert_eq!(
+            transactions("deposit, 1, 7, 2\ndeposit, 1, 8, 3\n")
+                .await
+                .unwrap(),
+            vec![(1, deposit(7, "2")), (2, deposit(8, "3"))]
+        );
+        // A first record that doesn't name any known column is not a header,
+        // it's parsed by position so a bad type is reported instead of being skipped.
+        match transactions("lol, 2, 5, 3.0\ndeposit, 1, 7, 2\n").await {
+            Err(ParseError::Type { line: 1, text, .. }) => assert_eq!(text, "lol"),
+            res => panic!("expected a Type error, got {:?}", res),
+        }
+        let options = ParseOptions {
+            on_error: ErrorPolicy::SkipSilently,
+            ..Default::default()
+        };
+        let input = "kind, who, id, how much\ndeposit, 1, 7, 2\n";
         assert_eq!(
-            parse(br).try_next().await.unwrap_err().to_string(),
-            field_error("client", 1)().to_string()
+            parse(BufReader::new(input.as_bytes()), options)
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap(),
+            vec![(2, deposit(7, "2"))]
         );
+
+        match transactions("type, client, amount\ndeposit, 1, 2\n").await {
+            Err(ParseError::MissingColumn { line: 1, text, .. }) => assert_eq!(text, "tx"),
+            res => panic!("expected a MissingColumn error, got {:?}", res),
+        }
+        match transactions("type, client, tx, Client\n").await {
+            Err(ParseError::DuplicateColumn {
+                line: 1,
+                column: 18,
+                text,
+            }) => assert_eq!(text, "Client"),
+            res => panic!("expected a DuplicateColumn error, got {:?}", res),
+        }
+        // Header errors abort regardless of the error policy.
+        let options = ParseOptions {
+            on_error: ErrorPolicy::SkipSilently,
+            ..Default::default()
+        };
+        let input = "type, amount\ndeposit, 1\n";
+        assert!(parse(BufReader::new(input.as_bytes()), options)
+            .try_next()
+            .await
+            .is_err());
     }
 
     #[smol_potat::test]
-    async fn invalid_tx() {
-        // String
-        let br = BufReader::new(
-            r#"
-        deposit, 4, bar
-        "#
-            .as_bytes(),
+    async fn error_policy() {
+        let input = "type, client, tx, amount\n\
+                     deposit, 1, 1, 1\n\
+                     lol, 2, 5, 3.0\n\
+                     deposit, 1, 2, 2\n\
+                     deposit, 1, 3\n\
+                     deposit, 1, 4, 4\n";
+        let lines = |on_error| async move {
+            let options = ParseOptions {
+                on_error,
+                ..Default::default()
+            };
+            let mut txs = parse(BufReader::new(input.as_bytes()), options);
+            let mut lines = Vec::new();
+            loop {
+                match txs.try_next().await {
+                    Ok(Some((line, _))) => lines.push(Ok(line)),
+                    Ok(None) => return lines,
+                    Err(err) => {
+                        lines.push(Err(err.to_string()));
+                        return lines;
+                    }
+                }
+            }
+        };
+        let type_error = || Err("Missing or invalid type 'lol' in line 3 at byte 0".to_string());
+        let amount_error = || Err("Invalid amount '' in line 5 at byte 13: missing".to_string());
+
+        assert_eq!(lines(ErrorPolicy::Abort).await, vec![Ok(2), type_error()]);
+        assert_eq!(
+            lines(ErrorPolicy::SkipSilently).await,
+            vec![Ok(2), Ok(4), Ok(6)]
         );
         assert_eq!(
-            parse(br).try_next().await.unwrap_err().to_string(),
-            field_error("tx", 1)().to_string()
+            lines(ErrorPolicy::SkipAndReport).await,
+            vec![Ok(2), Ok(4), Ok(6)]
         );
-
-        // Negative number
-        let br = BufReader::new(
-            r#"
-        deposit,5, -5
-        "#
-            .as_bytes(),
+        assert_eq!(
+            lines(ErrorPolicy::AbortAfter(1)).await,
+            vec![Ok(2), type_error()]
         );
         assert_eq!(
-            parse(br).try_next().await.unwrap_err().to_string(),
-            field_error("tx", 1)().to_string()
+            lines(ErrorPolicy::AbortAfter(2)).await,
+            vec![Ok(2), Ok(4), amount_error()]
+        );
+        assert_eq!(
+            lines(ErrorPolicy::AbortAfter(3)).await,
+            vec![Ok(2), Ok(4), Ok(6)]
         );
 
-        // Overflow
-        let string = format!(
-            r#"
-        deposit,6, {}
-        "#,
-            std::u32::MAX as u64 + 1
+        assert_eq!("skip-and-report".parse(), Ok(ErrorPolicy::SkipAndReport));
+        assert_eq!("abort-after-10".parse(), Ok(ErrorPolicy::AbortAfter(10)));
+    }
+
+    #[smol_potat::test]
+    async fn amount_validation() {
+        async fn reason(row: &str, options: &ParseOptions) -> Option<InvalidAmount> {
+            let input = format!("type, client, tx, amount\n{}\n", row);
+            match error(input.as_bytes(), options.clone()).await {
+                ParseError::Amount { line, reason, .. } => {
+                    assert_eq!(line, 2);
+                    Some(reason)
+                }
+                _ => None,
+            }
+        }
+        let default = ParseOptions::default();
+        use InvalidAmount::*;
+
+        assert_eq!(reason("deposit, 1, 1, -5", &default).await, Some(Negative));
+        assert_eq!(
+            reason("deposit, 1, 1, -0.0001", &default).await,
+            Some(Negative)
         );
-        let br = BufReader::new(string.as_bytes());
+        assert_eq!(reason("withdrawal, 1, 1, 0", &default).await, Some(Zero));
+        // Negative amounts that round to zero are still negative.
         assert_eq!(
-            parse(br).try_next().await.unwrap_err().to_string(),
-            field_error("tx", 1)().to_string()
+            reason("deposit, 1, 1, -0.00001", &default).await,
+            Some(Negative)
         );
-
-        // Empty
-        let br = BufReader::new(
-            r#"
-        deposit, 4,,
-        "#
-            .as_bytes(),
+        assert_eq!(reason("deposit, 1, 1, -0.0", &default).await, Some(Zero));
+        assert_eq!(reason("deposit, 1, 1, 0.00001", &default).await, Some(Zero));
+        assert_eq!(reason("dispute, 1, 1, 0.0", &default).await, Some(Zero));
+        assert_eq!(
+            reason("deposit, 1, 1, NaN", &default).await,
+            Some(NotFinite)
         );
         assert_eq!(
-            parse(br).try_next().await.unwrap_err().to_string(),
-            field_error("tx", 1)().to_string()
+            reason("deposit, 1, 1, -inf", &default).await,
+            Some(NotFinite)
         );
-    }
-    #[smol_potat::test]
-    async fn invalid_amount() {
-        // String
-        let br = BufReader::new(
-            r#"
-        deposit, 2, 3 , eheh 
-        "#
-            .as_bytes(),
+        assert_eq!(
+            reason("deposit, 1, 1, Infinity", &default).await,
+            Some(NotFinite)
         );
         assert_eq!(
-            parse(br).try_next().await.unwrap_err().to_string(),
-            field_error("amount", 1)().to_string()
+            reason("deposit, 1, 1, nope", &default).await,
+            Some(NotANumber)
         );
+        assert_eq!(reason("deposit, 1, 1", &default).await, Some(Missing));
 
-        // Empty
-        let br = BufReader::new(
-            r#"
-        deposit, 3,4,5
-        deposit, 2, 3 , 
-        "#
-            .as_bytes(),
+        let options = ParseOptions {
+            max_decimals: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            reason("deposit, 1, 1, 1.234", &options).await,
+            Some(TooPrecise(2))
         );
-        let mut txs = parse(br);
-        txs.try_next().await.unwrap();
         assert_eq!(
-            txs.try_next().await.unwrap_err().to_string(),
-            field_error("amount", 2)().to_string()
+            reason("deposit, 1, 1, -0.00001", &options).await,
+            Some(Negative)
+        );
+        let input = "type, client, tx, amount\ndeposit, 1, 1, 1.2300\n";
+        assert_eq!(
+            parse(BufReader::new(input.as_bytes()), options)
+                .try_next()
+                .await
+                .unwrap()
+                .unwrap()
+                .1,
+            Transaction::Deposit {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: amount("1.23"),
+            }
         );
     }
     #[smol_potat::test]
     async fn test_parse() {
         let br = BufReader::new(
-            r#"
+            r#"type, client, tx, amount, audit
         deposit, 1.6 , 3.3 , 5.7  
         withdrawal,2,5,9 
               dispute    ,   8       ,    4   
         resolve, 9, 30,
         chargeback, 24, 2000   
+        dispute, 3, 7, 1.25
+        freeze, 4, 8, , alice: fraud investigation #42
+        unlock, 4, 9
         "#
             .as_bytes(),
         );
-        let mut txs = parse(br);
+        let mut txs = parse(br, ParseOptions::default());
         assert_eq!(
             txs.try_next().await.unwrap().unwrap(),
-            Transaction::Deposit {
-                client: ClientId(1),
-                tx: TransactionId(3),
-                amount: 5.7
-            }
+            (
+                2,
+                Transaction::Deposit {
+                    client: ClientId(1),
+                    tx: TransactionId(3),
+                    amount: amount("5.7")
+                }
+            )
         );
 
         assert_eq!(
             txs.try_next().await.unwrap().unwrap(),
-            Transaction::Withdrawal {
-                client: ClientId(2),
-                tx: TransactionId(5),
-                amount: 9.
-            }
+            (
+                3,
+                Transaction::Withdrawal {
+                    client: ClientId(2),
+                    tx: TransactionId(5),
+                    amount: amount("9")
+                }
+            )
         );
         assert_eq!(
-            txs.try_next().await.unwrap().unwrap(),
+            txs.try_next().await.unwrap().unwrap().1,
             Transaction::Dispute {
                 client: ClientId(8),
                 tx: TransactionId(4),
+                amount: None,
             }
         );
         assert_eq!(
-            txs.try_next().await.unwrap().unwrap(),
+            txs.try_next().await.unwrap().unwrap().1,
             Transaction::Resolve {
                 client: ClientId(9),
                 tx: TransactionId(30),
+                amount: None,
             }
         );
         assert_eq!(
-            txs.try_next().await.unwrap().unwrap(),
+            txs.try_next().await.unwrap().unwrap().1,
             Transaction::ChargeBack {
                 client: ClientId(24),
                 tx: TransactionId(2000),
+                amount: None,
+            }
+        );
+        assert_eq!(
+            txs.try_next().await.unwrap().unwrap().1,
+            Transaction::Dispute {
+                client: ClientId(3),
+                tx: TransactionId(7),
+                amount: Some(amount("1.25")),
+            }
+        );
+        assert_eq!(
+            txs.try_next().await.unwrap().unwrap().1,
+            Transaction::Freeze {
+                client: ClientId(4),
+                tx: TransactionId(8),
+                audit: Some("alice: fraud investigation #42".to_string()),
+            }
+        );
+        assert_eq!(
+            txs.try_next().await.unwrap().unwrap().1,
+            Transaction::Unlock {
+                client: ClientId(4),
+                tx: TransactionId(9),
+                audit: None,
             }
         );
     }
@@ -408,7 +1723,7 @@ mod tests {
         let mut bytes = [0u8; 1024];
         for _ in 0..1000usize {
             rng.fill(&mut bytes);
-            let mut txs = parse(BufReader::new(&bytes[..]));
+            let mut txs = parse(BufReader::new(&bytes[..]), ParseOptions::default());
             while let Ok(Some(i)) = txs.try_next().await {
                 bencher::black_box(i);
             }
@@ -422,7 +1737,7 @@ mod tests {
             Transaction::Deposit {
                 client: ClientId(1),
                 tx: TransactionId(1),
-                amount: 3.4
+                amount: amount("3.4")
             }
             .to_csv()
         );
@@ -432,7 +1747,7 @@ mod tests {
             Transaction::Withdrawal {
                 client: ClientId(5),
                 tx: TransactionId(10),
-                amount: 34.
+                amount: amount("34")
             }
             .to_csv()
         );
@@ -442,6 +1757,17 @@ mod tests {
             Transaction::Dispute {
                 client: ClientId(59),
                 tx: TransactionId(999),
+                amount: None,
+            }
+            .to_csv()
+        );
+
+        assert_eq!(
+            "dispute,59,999,0.5",
+            Transaction::Dispute {
+                client: ClientId(59),
+                tx: TransactionId(999),
+                amount: Some(amount("0.5")),
             }
             .to_csv()
         );
@@ -451,6 +1777,7 @@ mod tests {
             Transaction::Resolve {
                 client: ClientId(89),
                 tx: TransactionId(7),
+                amount: None,
             }
             .to_csv()
         );
@@ -460,8 +1787,94 @@ mod tests {
             Transaction::ChargeBack {
                 client: ClientId(34040),
                 tx: TransactionId(33304304),
+                amount: None,
             }
             .to_csv()
         );
+
+        assert_eq!(
+            "freeze,3,12,,bob: chargeback review",
+            Transaction::Freeze {
+                client: ClientId(3),
+                tx: TransactionId(12),
+                audit: Some("bob: chargeback review".to_string()),
+            }
+            .to_csv()
+        );
+
+        assert_eq!(
+            "freeze,3,14,,\"carol: \"\"chargeback\"\", review\"",
+            Transaction::Freeze {
+                client: ClientId(3),
+                tx: TransactionId(14),
+                audit: Some("carol: \"chargeback\", review".to_string()),
+            }
+            .to_csv()
+        );
+
+        assert_eq!(
+            "unlock,3,13,,",
+            Transaction::Unlock {
+                client: ClientId(3),
+                tx: TransactionId(13),
+                audit: None,
+            }
+            .to_csv()
+        );
+    }
+
+    #[smol_potat::test]
+    async fn output_serialization() {
+        let mut wri = csv_async::AsyncSerializer::from_writer(Vec::new());
+        for output in [
+            Output {
+                client: ClientId(1),
+                available: amount("0.3"),
+                held: amount("0.00001"),
+                total: amount("0.3"),
+                locked: false,
+                stats: Default::default(),
+            },
+            Output {
+                client: ClientId(2),
+                available: amount("-100000"),
+                held: amount("12.34567"),
+                total: amount("-99987.6543"),
+                locked: true,
+                stats: Default::default(),
+            },
+        ] {
+            wri.serialize(output).await.unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(wri.into_inner().await.unwrap()).unwrap(),
+            "client,available,held,total,locked\n\
+             1,0.3000,0.0000,0.3000,false\n\
+             2,-100000.0000,12.3457,-99987.6543,true\n"
+        );
+    }
+
+    #[smol_potat::test]
+    async fn rounding() {
+        let input = "type, client, tx, amount\ndeposit, 1, 1, 0.00025\n";
+        for (rounding, expected) in [
+            (Rounding::HalfEven, "0.0002"),
+            (Rounding::HalfUp, "0.0003"),
+            (Rounding::Down, "0.0002"),
+        ] {
+            let options = ParseOptions {
+                rounding,
+                ..Default::default()
+            };
+            let mut txs = parse(BufReader::new(input.as_bytes()), options);
+            assert_eq!(
+                txs.try_next().await.unwrap().unwrap().1,
+                Transaction::Deposit {
+                    client: ClientId(1),
+                    tx: TransactionId(1),
+                    amount: amount(expected)
+                }
+            );
+        }
     }
 }
@@ -0,0 +1,211 @@
+use futures::TryStream;
+use serde::Deserialize;
+use serde_json::value::RawValue;
+use smol::io::AsyncBufRead;
+use std::borrow::Cow;
+
+use crate::io::{
+    parse_fields, parse_records, Column, Field, ParseError, ParseOptions, RecordScanner,
+    Transaction,
+};
+
+/// The members of a JSON Lines record that [parse_jsonl] understands, unknown members are ignored.
+#[derive(Deserialize)]
+struct Record<'a> {
+    #[serde(rename = "type", borrow, default)]
+    ty: Option<&'a RawValue>,
+    #[serde(borrow, default)]
+    client: Option<&'a RawValue>,
+    #[serde(borrow, default)]
+    tx: Option<&'a RawValue>,
+    #[serde(borrow, default)]
+    amount: Option<&'a RawValue>,
+    #[serde(borrow, default)]
+    audit: Option<&'a RawValue>,
+}
+
+/// Returns a stream of the [Transactions](Transaction) in the JSON Lines `read`,
+/// each one paired with the number of the line it was found in, starting from 1.
+///
+/// Every line is an object like `{"type":"deposit","client":1,"tx":1,"amount":"1.5"}`.
+/// The members are the columns of the csv format and are validated the same way,
+/// numbers and strings are both accepted and amounts are read from their text so they keep their precision.
+/// Blank lines are skipped.
+///
+/// Errors are reported like in [parse](crate::io::parse), their column is the byte offset of the value.
+pub fn parse_jsonl(
+    read: impl AsyncBufRead + Unpin,
+    options: ParseOptions,
+) -> impl TryStream<Ok = (usize, Transaction), Error = ParseError> {
+    let on_error = options.on_error;
+    parse_records(read, RecordScanner::lines(), on_error, move |i, line| {
+        parse_line(line, i, &options)
+    })
+}
+
+/// Parses `line`, the line `i` of the input, nothing is returned if it is blank.
+fn parse_line(
+    line: &[u8],
+    i: usize,
+    options: &ParseOptions,
+) -> Result<Option<(usize, Transaction)>, ParseError> {
+    if line.trim_ascii().is_empty() {
+        return Ok(None);
+    }
+    let record: Record = serde_json::from_slice(line).map_err(|err| {
+        // serde_json counts columns from 1.
+        let column = err.column().saturating_sub(1).min(line.len());
+        ParseError::Syntax {
+            line: i,
+            column,
+            text: String::from_utf8_lossy(&line[column..]).into_owned(),
+        }
+    })?;
+
+    let mut fields: [Option<Field>; 5] = Default::default();
+    for (column, value) in [
+        (Column::Type, record.ty),
+        (Column::Client, record.client),
+        (Column::Tx, record.tx),
+        (Column::Amount, record.amount),
+        (Column::Audit, record.audit),
+    ]
+    .iter()
+    {
+        fields[*column as usize] = value.and_then(|value| field(line, value));
+    }
+    Ok(Some((i, parse_fields(fields, line.len(), i, options)?)))
+}
+
+/// Returns the [Field] for `value`, which is part of `line`, [None] if it is null.
+///
+/// Strings are unescaped, anything else is kept as written.
+fn field<'a>(line: &[u8], value: &'a RawValue) -> Option<Field<'a>> {
+    let raw = value.get();
+    let column = raw.as_ptr() as usize - line.as_ptr() as usize;
+    let text = match raw {
+        "null" => return None,
+        _ if raw.starts_with('"') => match serde_json::from_str::<&str>(raw) {
+            Ok(text) => Cow::Borrowed(text.as_bytes()),
+            // Escaped strings have to be copied.
+            Err(_) => Cow::Owned(serde_json::from_str::<String>(raw).ok()?.into_bytes()),
+        },
+        _ => Cow::Borrowed(raw.as_bytes()),
+    };
+    Some(Field { column, text })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        amount::Amount,
+        io::{ClientId, ErrorPolicy, InvalidAmount, TransactionId},
+    };
+    use futures::{io::BufReader, TryStreamExt};
+
+    #[smol_potat::test]
+    async fn test_parse_jsonl() {
+        let input = r#"{"type":"deposit","client":1,"tx":1,"amount":"1.5"}
+
+            {"tx": 2, "client": "1", "type": "Withdrawal", "amount": 0.12345, "source": "bus"}
+{"type":"dispute","client":1,"tx":1,"amount":null}
+{"type":"freeze","client":1,"tx":3,"audit":"bob: \"fraud\"\nsee #42"}"#;
+        let transactions: Vec<_> =
+            parse_jsonl(BufReader::new(input.as_bytes()), Default::default())
+                .try_collect()
+                .await
+                .unwrap();
+        assert_eq!(
+            transactions,
+            vec![
+                (
+                    1,
+                    Transaction::Deposit {
+                        client: ClientId(1),
+                        tx: TransactionId(1),
+                        amount: "1.5".parse::<Amount>().unwrap(),
+                    }
+                ),
+                (
+                    3,
+                    Transaction::Withdrawal {
+                        client: ClientId(1),
+                        tx: TransactionId(2),
+                        amount: "0.1234".parse::<Amount>().unwrap(),
+                    }
+                ),
+                (
+                    4,
+                    Transaction::Dispute {
+                        client: ClientId(1),
+                        tx: TransactionId(1),
+                        amount: None,
+                    }
+                ),
+                (
+                    5,
+                    Transaction::Freeze {
+                        client: ClientId(1),
+                        tx: TransactionId(3),
+                        audit: Some("bob: \"fraud\"\nsee #42".to_string()),
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[smol_potat::test]
+    async fn test_errors() {
+        async fn error(input: &str) -> ParseError {
+            parse_jsonl(BufReader::new(input.as_bytes()), Default::default())
+                .try_collect::<Vec<_>>()
+                .await
+                .unwrap_err()
+        }
+
+        let err = error(r#"{"type":"deposit","client":-1,"tx":1,"amount":"1"}"#).await;
+        assert!(
+            matches!(err, ParseError::Client { line: 1, column: 27, ref text } if text == "-1")
+        );
+        let err = error("{}\n{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"-1\"}").await;
+        assert!(
+            matches!(err, ParseError::Type { line: 1, column: 2, ref text } if text.is_empty())
+        );
+        let err = error(r#"{"type":"deposit","client":1,"tx":1,"amount":"-1"}"#).await;
+        assert!(matches!(
+            err,
+            ParseError::Amount {
+                line: 1,
+                column: 45,
+                reason: InvalidAmount::Negative,
+                ..
+            }
+        ));
+        let err = error(r#"{"type":"deposit","client":1,"tx":1}"#).await;
+        assert!(matches!(
+            err,
+            ParseError::Amount {
+                line: 1,
+                reason: InvalidAmount::Missing,
+                ..
+            }
+        ));
+        let err = error("{\"type\":\"deposit\",\n").await;
+        assert!(matches!(err, ParseError::Syntax { line: 1, .. }));
+        let err = error(r#"{"type":"deposit","type":"withdrawal"}"#).await;
+        assert!(matches!(err, ParseError::Syntax { line: 1, .. }));
+
+        let options = ParseOptions {
+            on_error: ErrorPolicy::SkipSilently,
+            ..Default::default()
+        };
+        let input = "nope\n{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"1\"}\n";
+        let transactions: Vec<_> = parse_jsonl(BufReader::new(input.as_bytes()), options)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(transactions[0].0, 2);
+    }
+}
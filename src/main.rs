@@ -1,34 +1,107 @@
-use std::process;
+use std::{fmt::Debug, process, str::FromStr};
+mod amount;
+mod binlog;
+#[cfg(any(test, feature = "multithreaded"))]
+mod chunked;
 mod client_state;
+mod compression;
+mod events;
 mod generate;
 mod generate_random;
 mod io;
+mod jsonl;
+mod output;
+mod rejections;
 mod transaction_processor;
 use generate::generate;
 use generate_random::generate_random;
-use transaction_processor::process;
+use io::InputFormat;
+use transaction_processor::{convert, process, Options};
 
-/// Arguments: `<file>` `<operation>`
+/// Arguments: `<file>` `<operation>` `[<out>]` `[options]`
 ///
 /// `<file>`
-/// Path to the file to operate on.
+/// Path to the file to operate on, `-` processes the transactions read from stdin.
+/// Gzip and zstd compressed inputs are decompressed on the fly, generated files are compressed if `<file>` ends in `.gz` or `.zst`.
 ///
 /// `<operation>`
-/// Can be "", "gen" or "genrandom"
+/// Can be "", "convert", "gen" or "genrandom"
 /// "" -> Processes the transactions in `<file>` and outputs the result to stdout.
+/// "convert" -> Converts the transactions in `<file>` to a binary log at `<out>`, compressed like generated files.
+/// Binary logs are detected when processing or converting, whatever the input format.
 /// "gen" -> Generates transactions using a smart-ish algorithm and outputs them to `<file>`.
 /// "genrandom" -> Generates transactions using purely random values and outputs them to `<file>`.
+///
+/// `[options]`
+/// Can appear anywhere in the arguments.
+/// "--rounding <half-even|half-up|half-down|down>" -> How input amounts with more than four decimal places are rounded, half-even by default.
+/// "--max-decimals `<n>`" -> Rejects input amounts with more than `n` decimal places instead of rounding them, `n` can be at most 4.
+/// "--on-error <abort|skip-and-report|skip-silently|abort-after-n>" -> What to do with lines that can't be parsed, abort by default.
+/// skip-and-report and abort-after-n write the skipped lines' errors to stderr, abort-after-n aborts on the n-th error.
+/// "--aliases <alias=type,...>" -> Extra names accepted in the type column, e.g. "credit=deposit,cb=chargeback".
+/// Types and aliases are matched ignoring case, `_` and `-`.
+/// "--disputes <deposits|deposits-and-withdrawals>" -> Which transactions can be disputed, deposits by default.
+/// "--events <none|stderr|file>" -> Where to report rejections and state changes, nowhere by default.
+/// stderr only gets the rejections and the changes to locked accounts, a file gets every event.
+/// "--rejections `<file>`" -> Writes every rejected transaction to a csv file, with its line number and a reason code.
+/// "--input-format <csv|jsonl>" -> Format of the input, picked from the extension of `<file>` by default:
+/// `.jsonl` and `.ndjson` files are JSON Lines, anything else is csv.
+/// "--output-format <csv|jsonl|json>" -> Writes the account states as csv, one JSON object per line or a JSON array, csv by default.
+/// Amounts are always strings with four decimal places.
+/// "--output-order <client|unsorted>" -> Writes the account states sorted by client id, the default, or in no particular order.
+/// "--output-columns <basic|extended>" -> Extended adds deposits, open_disputes, chargebacks, deposited, withdrawn,
+/// last_tx and lock_reason after the basic columns, basic by default.
+/// "--output `<file>`" -> Writes the account states to `<file>` instead of stdout.
+/// They are written to a temporary file in the same directory, which only replaces `<file>` once everything succeeded.
+/// "--mmap" -> Reads `<file>` through a memory map, faster for big local files. `<file>` must not change while it's processed.
 async fn async_main() {
-    let mut args = std::env::args().into_iter().skip(1);
-    let file = args
+    let mut options = Options::default();
+    let mut positional = Vec::new();
+    let mut format = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--rounding" => options.parse.rounding = option_value(&arg, args.next()),
+            "--max-decimals" => {
+                let max = option_value(&arg, args.next());
+                if max > amount::DECIMALS {
+                    panic!("{} can't be more than {}", arg, amount::DECIMALS);
+                }
+                options.parse.max_decimals = Some(max);
+            }
+            "--on-error" => options.parse.on_error = option_value(&arg, args.next()),
+            "--aliases" => options.parse.aliases = option_value(&arg, args.next()),
+            "--disputes" => options.disputes = option_value(&arg, args.next()),
+            "--events" => options.events = option_value(&arg, args.next()),
+            "--rejections" => options.rejections = Some(option_value(&arg, args.next())),
+            "--input-format" => format = Some(option_value(&arg, args.next())),
+            "--output-format" => options.output_format = option_value(&arg, args.next()),
+            "--output-order" => options.output_order = option_value(&arg, args.next()),
+            "--output-columns" => options.output_columns = option_value(&arg, args.next()),
+            "--output" => options.output = Some(option_value(&arg, args.next())),
+            "--mmap" => options.mmap = true,
+            _ => positional.push(arg),
+        }
+    }
+
+    let mut positional = positional.into_iter();
+    let file = positional
         .next()
-        .expect("Please provide a path to a csv file in the first argument");
+        .expect("Please provide a path to a csv file, or - for stdin, in the first argument");
+    options.format = format.unwrap_or_else(|| InputFormat::from_path(&file));
 
-    let res = match args.next().as_deref() {
-        None => process(&file).await,
+    let res = match positional.next().as_deref() {
+        None => process(&file, &options).await,
+        Some("convert") => {
+            let out = positional
+                .next()
+                .expect("Please provide the path of the binary log after 'convert'");
+            convert(&file, &out, &options).await
+        }
         Some("gen") => generate(&file).await,
         Some("genrandom") => generate_random(&file).await,
-        _ => panic!("The second argument can only be 'gen' or 'genrandom'"),
+        _ => panic!("The second argument can only be 'convert', 'gen' or 'genrandom'"),
     };
 
     if let Err(err) = res {
@@ -37,6 +110,18 @@ async fn async_main() {
     }
 }
 
+/// Parses the value of the command line option `name`.
+fn option_value<T: FromStr>(name: &str, value: Option<String>) -> T
+where
+    T::Err: Debug,
+{
+    let value = value.unwrap_or_else(|| panic!("{} requires a value", name));
+    value
+        .parse()
+        .unwrap_or_else(|err| panic!("Invalid value '{}' for {}: {:?}", value, name, err))
+}
+
+// Must match transaction_processor::EXECUTOR_THREADS.
 #[cfg(feature = "multithreaded")]
 #[smol_potat::main(threads = 2)]
 async fn main() {
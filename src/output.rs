@@ -0,0 +1,332 @@
+use csv_async::AsyncSerializer;
+use futures::{AsyncWrite, AsyncWriteExt};
+use parse_display::{Display, FromStr};
+use serde::Serialize;
+use smol::fs::{self, File};
+use std::{
+    error::Error,
+    ffi::OsString,
+    io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::io::{ExtendedOutput, Output};
+
+#[derive(Display, FromStr, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[display(style = "lowercase")]
+/// The formats the account states can be written in.
+pub enum OutputFormat {
+    /// Comma separated values with a header.
+    #[default]
+    Csv,
+    /// One JSON object per line.
+    Jsonl,
+    /// A single JSON array of objects.
+    Json,
+}
+
+#[derive(Display, FromStr, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[display(style = "lowercase")]
+/// The order the account states are written in.
+pub enum OutputOrder {
+    /// Sorted by client id, so the same input always produces the same output.
+    #[default]
+    Client,
+    /// Whatever order the clients are stored in, which changes between runs and builds.
+    Unsorted,
+}
+
+#[derive(Display, FromStr, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[display(style = "lowercase")]
+/// The columns written for every account.
+pub enum OutputColumns {
+    /// client, available, held, total and locked.
+    #[default]
+    Basic,
+    /// The basic columns followed by the [ClientStats](crate::client_state::ClientStats) of the account,
+    /// see [ExtendedOutput].
+    Extended,
+}
+
+/// Writes [Outputs](Output) in an [OutputFormat].
+///
+/// Every format writes amounts as strings with exactly four decimal places,
+/// so they never lose precision in a JSON parser.
+pub struct OutputWriter<W: AsyncWrite + Unpin> {
+    sink: Sink<W>,
+    columns: OutputColumns,
+}
+enum Sink<W: AsyncWrite + Unpin> {
+    Csv(Box<AsyncSerializer<W>>),
+    Json {
+        wri: W,
+        /// Writes a single array instead of one object per line.
+        array: bool,
+        /// Number of [Outputs](Output) written so far.
+        written: usize,
+    },
+}
+impl<W: AsyncWrite + Unpin> OutputWriter<W> {
+    pub fn new(wri: W, format: OutputFormat, columns: OutputColumns) -> Self {
+        let sink = match format {
+            OutputFormat::Csv => Sink::Csv(Box::new(AsyncSerializer::from_writer(wri))),
+            OutputFormat::Jsonl | OutputFormat::Json => Sink::Json {
+                wri,
+                array: format == OutputFormat::Json,
+                written: 0,
+            },
+        };
+        OutputWriter { sink, columns }
+    }
+    /// Writes `output`.
+    pub async fn write(&mut self, output: &Output) -> Result<(), Box<dyn Error>> {
+        match self.columns {
+            OutputColumns::Basic => self.sink.write(output).await,
+            OutputColumns::Extended => self.sink.write(&ExtendedOutput(output)).await,
+        }
+    }
+    /// Writes whatever the format needs after the last [Output] and flushes the writer.
+    pub async fn finish(self) -> Result<W, Box<dyn Error>> {
+        match self.sink {
+            Sink::Csv(wri) => Ok(wri.into_inner().await.map_err(|err| err.into_error())?),
+            Sink::Json {
+                mut wri,
+                array,
+                written,
+            } => {
+                match (array, written) {
+                    (true, 0) => wri.write_all(b"[]\n").await?,
+                    (true, _) => wri.write_all(b"\n]\n").await?,
+                    (false, _) => {}
+                }
+                wri.flush().await?;
+                Ok(wri)
+            }
+        }
+    }
+}
+impl<W: AsyncWrite + Unpin> Sink<W> {
+    async fn write(&mut self, row: &impl Serialize) -> Result<(), Box<dyn Error>> {
+        match self {
+            Sink::Csv(wri) => wri.serialize(row).await?,
+            Sink::Json {
+                wri,
+                array,
+                written,
+            } => {
+                let separator: &[u8] = match (*array, *written) {
+                    (true, 0) => b"[\n",
+                    (true, _) => b",\n",
+                    (false, _) => b"",
+                };
+                let mut line = separator.to_vec();
+                serde_json::to_writer(&mut line, row)?;
+                if !*array {
+                    line.push(b'\n');
+                }
+                wri.write_all(&line).await?;
+                *written += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A file that is written under a temporary name next to `path`
+/// and only replaces the file at `path` once it's [committed](PendingFile::commit).
+///
+/// The temporary file is removed if the [PendingFile] is dropped without being committed,
+/// so nothing ever sees a partially written file at `path`.
+pub struct PendingFile {
+    file: File,
+    temp: PathBuf,
+    path: PathBuf,
+    committed: bool,
+}
+impl PendingFile {
+    /// Creates the temporary file for `path`, in the same directory so it can be renamed into place.
+    pub async fn create(path: &Path) -> io::Result<Self> {
+        let name = path.file_name().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the output path has no file name",
+            )
+        })?;
+        let mut temp = OsString::from(".");
+        temp.push(name);
+        temp.push(format!(".{}.tmp", std::process::id()));
+        let temp = path.with_file_name(temp);
+        Ok(PendingFile {
+            file: File::create(&temp).await?,
+            temp,
+            path: path.to_path_buf(),
+            committed: false,
+        })
+    }
+    /// Flushes the temporary file to disk and renames it to `path`, replacing whatever was there.
+    pub async fn commit(mut self) -> io::Result<()> {
+        self.file.flush().await?;
+        self.file.sync_all().await?;
+        fs::rename(&self.temp, &self.path).await?;
+        self.committed = true;
+        // The rename itself is only durable once the directory is synced.
+        #[cfg(unix)]
+        {
+            let dir = match self.path.parent() {
+                Some(dir) if dir != Path::new("") => dir,
+                _ => Path::new("."),
+            };
+            File::open(dir).await?.sync_all().await?;
+        }
+        Ok(())
+    }
+}
+impl Drop for PendingFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            // Nothing better to do with the error, the original error is more interesting.
+            let _ = std::fs::remove_file(&self.temp);
+        }
+    }
+}
+impl AsyncWrite for PendingFile {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.file).poll_write(cx, buf)
+    }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.file).poll_flush(cx)
+    }
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.file).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client_state::{ClientStats, LockReason},
+        io::{ClientId, TransactionId},
+    };
+
+    async fn write(format: OutputFormat, outputs: &[Output]) -> String {
+        write_columns(format, OutputColumns::Basic, outputs).await
+    }
+    async fn write_columns(
+        format: OutputFormat,
+        columns: OutputColumns,
+        outputs: &[Output],
+    ) -> String {
+        let mut wri = OutputWriter::new(Vec::new(), format, columns);
+        for output in outputs {
+            wri.write(output).await.unwrap();
+        }
+        String::from_utf8(wri.finish().await.unwrap()).unwrap()
+    }
+
+    #[smol_potat::test]
+    async fn test_formats() {
+        // Amount formatting is covered by the csv tests in io, these only check the JSON framing.
+        let output = |client, locked| Output {
+            client: ClientId(client),
+            available: "1.5".parse().unwrap(),
+            held: "0".parse().unwrap(),
+            total: "1.5".parse().unwrap(),
+            locked,
+            stats: Default::default(),
+        };
+        let outputs = vec![output(1, false), output(2, true)];
+        let first =
+            r#"{"client":1,"available":"1.5000","held":"0.0000","total":"1.5000","locked":false}"#;
+        let second =
+            r#"{"client":2,"available":"1.5000","held":"0.0000","total":"1.5000","locked":true}"#;
+
+        assert_eq!(
+            write(OutputFormat::Jsonl, &outputs).await,
+            format!("{}\n{}\n", first, second)
+        );
+        assert_eq!(
+            write(OutputFormat::Json, &outputs).await,
+            format!("[\n{},\n{}\n]\n", first, second)
+        );
+        assert_eq!(write(OutputFormat::Json, &[]).await, "[]\n");
+        assert_eq!(write(OutputFormat::Jsonl, &[]).await, "");
+
+        assert_eq!("jsonl".parse(), Ok(OutputFormat::Jsonl));
+        assert_eq!("unsorted".parse(), Ok(OutputOrder::Unsorted));
+    }
+
+    #[smol_potat::test]
+    async fn test_extended_columns() {
+        let outputs = vec![
+            Output {
+                client: ClientId(1),
+                available: "0.5".parse().unwrap(),
+                held: "2".parse().unwrap(),
+                total: "2.5".parse().unwrap(),
+                locked: true,
+                stats: ClientStats {
+                    deposits: 3,
+                    open_disputes: 1,
+                    chargebacks: 1,
+                    deposited: "10".parse().unwrap(),
+                    withdrawn: "1.25".parse().unwrap(),
+                    last_tx: Some(TransactionId(42)),
+                    lock_reason: Some(LockReason::ChargeBack),
+                },
+            },
+            Output {
+                client: ClientId(2),
+                available: "0".parse().unwrap(),
+                held: "0".parse().unwrap(),
+                total: "0".parse().unwrap(),
+                locked: false,
+                stats: Default::default(),
+            },
+        ];
+
+        assert_eq!(
+            write_columns(OutputFormat::Csv, OutputColumns::Extended, &outputs).await,
+            "client,available,held,total,locked,deposits,open_disputes,chargebacks,deposited,withdrawn,last_tx,lock_reason\n\
+             1,0.5000,2.0000,2.5000,true,3,1,1,10.0000,1.2500,42,chargeback\n\
+             2,0.0000,0.0000,0.0000,false,0,0,0,0.0000,0.0000,,\n"
+        );
+        assert_eq!(
+            write_columns(OutputFormat::Jsonl, OutputColumns::Extended, &outputs[1..]).await,
+            r#"{"client":2,"available":"0.0000","held":"0.0000","total":"0.0000","locked":false,"deposits":0,"open_disputes":0,"chargebacks":0,"deposited":"0.0000","withdrawn":"0.0000","last_tx":null,"lock_reason":null}"#
+                .to_string()
+                + "\n"
+        );
+        assert_eq!("extended".parse(), Ok(OutputColumns::Extended));
+    }
+
+    #[smol_potat::test]
+    async fn test_pending_file() {
+        let dir = std::env::temp_dir().join(format!("pending-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("result.csv");
+        std::fs::write(&path, "old").unwrap();
+
+        // Dropped without committing, the old file stays and the temporary file is removed.
+        let mut file = PendingFile::create(&path).await.unwrap();
+        file.write_all(b"partial").await.unwrap();
+        drop(file);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "old");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        let mut file = PendingFile::create(&path).await.unwrap();
+        file.write_all(b"new").await.unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "old");
+        file.commit().await.unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
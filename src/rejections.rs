@@ -0,0 +1,151 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use crate::{events::Rejection, io::Transaction};
+
+#[derive(Debug)]
+/// Writes every rejected [Transaction] to a csv file with its line number and the reason it was rejected.
+///
+/// Rows look like `12,"withdrawal,1,5,3.5",insufficient_funds`, the transaction is
+/// quoted because it is written with [Transaction::to_csv].
+///
+/// Rows are written in the order they are recorded, [shards](RejectionReport::shard) let
+/// several tasks record rejections and still get the rows in the order of the input.
+pub struct RejectionReport {
+    inner: Arc<Mutex<RejectionReportInner>>,
+    /// Rows recorded by a shard and not handed to the report yet, with their line.
+    rows: Option<Vec<(usize, String)>>,
+}
+#[derive(Debug)]
+struct RejectionReportInner {
+    wri: BufWriter<File>,
+    /// Rows handed over by shards, written in line order when the report is flushed.
+    sharded: Vec<(usize, String)>,
+    error: Option<io::Error>,
+}
+impl RejectionReport {
+    /// Creates or truncates the file at `path`, writes the header and returns a report that writes to it.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let mut wri = BufWriter::new(File::create(path)?);
+        writeln!(wri, "line,transaction,reason")?;
+        Ok(Self {
+            inner: Arc::new(Mutex::new(RejectionReportInner {
+                wri,
+                sharded: Vec::new(),
+                error: None,
+            })),
+            rows: None,
+        })
+    }
+    /// Returns a report that keeps its rows until it's [flushed](RejectionReport::flush)
+    /// and then hands them to this one, which writes the rows of every shard sorted by line
+    /// the next time it's flushed.
+    #[cfg_attr(not(feature = "multithreaded"), allow(dead_code))]
+    pub fn shard(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            rows: Some(Vec::new()),
+        }
+    }
+    /// Writes `tx`, found in `line` of the input, rejected because of `reason`.
+    pub fn record(&mut self, line: usize, tx: &Transaction, reason: Rejection) {
+        let row = format!(
+            "{},\"{}\",{}",
+            line,
+            tx.to_csv().replace('"', "\"\""),
+            reason
+        );
+        if let Some(rows) = &mut self.rows {
+            rows.push((line, row));
+            return;
+        }
+        let mut inner = self.inner.lock().expect("poisoned rejection report");
+        if inner.error.is_none() {
+            if let Err(err) = writeln!(inner.wri, "{}", row) {
+                inner.error = Some(err)
+            }
+        }
+    }
+    /// Makes sure every rejection has been written out, or handed to the report for shards.
+    ///
+    /// Returns the first error encountered while writing rejections, if any.
+    pub fn flush(&mut self) -> io::Result<()> {
+        let mut inner = self.inner.lock().expect("poisoned rejection report");
+        if let Some(rows) = &mut self.rows {
+            inner.sharded.append(rows);
+            return Ok(());
+        }
+        let mut sharded = std::mem::take(&mut inner.sharded);
+        // Every line holds one transaction, which is rejected at most once.
+        sharded.sort_unstable_by_key(|(line, _)| *line);
+        for (_, row) in sharded {
+            if inner.error.is_none() {
+                if let Err(err) = writeln!(inner.wri, "{}", row) {
+                    inner.error = Some(err)
+                }
+            }
+        }
+        match inner.error.take() {
+            Some(err) => Err(err),
+            None => inner.wri.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::{ClientId, TransactionId};
+
+    #[test]
+    fn test_report() {
+        let path = std::env::temp_dir().join(format!("rejections-{}.csv", std::process::id()));
+        let mut report = RejectionReport::create(&path).unwrap();
+        report.record(
+            3,
+            &Transaction::Withdrawal {
+                client: ClientId(1),
+                tx: TransactionId(5),
+                amount: "3.5".parse().unwrap(),
+            },
+            Rejection::InsufficientFunds,
+        );
+        // Shards are written in line order, whatever order they are flushed in.
+        let (mut first, mut second) = (report.shard(), report.shard());
+        first.record(
+            12,
+            &Transaction::Withdrawal {
+                client: ClientId(1),
+                tx: TransactionId(6),
+                amount: "1".parse().unwrap(),
+            },
+            Rejection::InsufficientFunds,
+        );
+        second.record(
+            7,
+            &Transaction::Unlock {
+                client: ClientId(2),
+                tx: TransactionId(9),
+                audit: Some("alice: \"false\" alarm".to_string()),
+            },
+            Rejection::NotLocked,
+        );
+        first.flush().unwrap();
+        second.flush().unwrap();
+        report.flush().unwrap();
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            written,
+            "line,transaction,reason\n\
+             3,\"withdrawal,1,5,3.5\",insufficient_funds\n\
+             7,\"unlock,2,9,,\"\"alice: \"\"\"\"false\"\"\"\" alarm\"\"\",not_locked\n\
+             12,\"withdrawal,1,6,1\",insufficient_funds\n"
+        );
+    }
+}
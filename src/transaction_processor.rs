@@ -1,61 +1,195 @@
-use rustc_hash::FxHashMap;
+use rustc_hash::{FxHashMap, FxHashSet};
 use std::iter::Map;
 
 #[cfg(feature = "multithreaded")]
 use futures::StreamExt;
-use smol::io::BufReader;
 #[cfg(feature = "multithreaded")]
 use smol::{
     channel::{bounded, Receiver},
     spawn,
 };
 
+#[cfg(feature = "multithreaded")]
+use crate::chunked::{parse_chunked, parse_mapped};
+#[cfg(not(feature = "multithreaded"))]
+use crate::io::parse;
 use crate::{
-    client_state::ClientState,
-    io::{parse, ClientId, Output, Transaction},
+    binlog,
+    client_state::{ClientState, DisputePolicy},
+    compression::{gzip_decoder, zstd_decoder, Compression},
+    events::{
+        DuplicateTransaction, EventTarget, FileEvents, LedgerEvent, LedgerEvents, NoopEvents,
+        Rejection, StderrEvents,
+    },
+    io::{ClientId, InputFormat, Output, ParseError, ParseOptions, Transaction, TransactionId},
+    jsonl::parse_jsonl,
+    output::{OutputColumns, OutputFormat, OutputOrder, OutputWriter, PendingFile},
+    rejections::RejectionReport,
+};
+use futures::{
+    io::{AsyncReadExt, Chain, Cursor},
+    pin_mut,
+    stream::{TryStream, TryStreamExt},
+};
+use memmap2::Mmap;
+use smol::{
+    fs::File,
+    io::{AsyncBufRead, AsyncWrite, BufReader},
+    Unblock,
 };
-use futures::stream::TryStreamExt;
-use smol::{fs::File, Unblock};
-use std::error::Error;
+use std::{error::Error, path::PathBuf};
+
+#[derive(Default)]
+/// Keeps track of the [TransactionIds](TransactionId) used by every Deposit, Withdrawal, Freeze and Unlock.
+struct TransactionRegistry {
+    ids: FxHashSet<TransactionId>,
+}
+impl TransactionRegistry {
+    /// Registers the id of `tx` if it is a Deposit, a Withdrawal, a Freeze or an Unlock.
+    ///
+    /// Returns false if the id had already been registered, by any client and any of those kinds.
+    /// Disputes, resolves and chargebacks refer to the id of another transaction so they are never registered.
+    fn register(&mut self, tx: &Transaction) -> bool {
+        match tx {
+            Transaction::Deposit { tx, .. }
+            | Transaction::Withdrawal { tx, .. }
+            | Transaction::Freeze { tx, .. }
+            | Transaction::Unlock { tx, .. } => self.ids.insert(*tx),
+            Transaction::Dispute { .. }
+            | Transaction::Resolve { .. }
+            | Transaction::ChargeBack { .. } => true,
+        }
+    }
+    /// Registers `tx`, found in `line` of the input, and reports it to `events` and `rejections` if it is a duplicate.
+    ///
+    /// Returns false if `tx` is a duplicate and must not be processed.
+    fn admit<E: LedgerEvents>(
+        &mut self,
+        line: usize,
+        tx: &Transaction,
+        events: &mut E,
+        rejections: &mut Option<RejectionReport>,
+    ) -> bool {
+        if self.register(tx) {
+            return true;
+        }
+        events.record(LedgerEvent::DuplicateTransaction(DuplicateTransaction {
+            client: tx.client(),
+            tx: tx.id(),
+        }));
+        if let Some(rejections) = rejections {
+            rejections.record(line, tx, Rejection::DuplicateTransaction)
+        }
+        false
+    }
+}
 
 /// Manages the state of a group of clients.
 ///
 /// Can be used single threaded by simply calling [TransactionProcessor::process]
 /// or multi threaded by providing a [Receiver] and calling [TransactionProcessor::run].
-struct TransactionProcessor {
+///
+/// In single threaded mode transactions that reuse the id of a previous Deposit, Withdrawal, Freeze or Unlock are rejected,
+/// in multi threaded mode that check happens before the transactions are sent to the processors,
+/// so it covers the clients of every processor.
+///
+/// Every state change and rejection is reported to `events`,
+/// rejected transactions are also written to `rejections` if there is one.
+struct TransactionProcessor<E: LedgerEvents = NoopEvents> {
     clients: FxHashMap<ClientId, ClientState>,
+    #[cfg(not(feature = "multithreaded"))]
+    registry: TransactionRegistry,
+    policy: DisputePolicy,
+    events: E,
+    rejections: Option<RejectionReport>,
     #[cfg(feature = "multithreaded")]
-    rx: Receiver<Transaction>,
+    rx: Receiver<(usize, Transaction)>,
 }
-impl TransactionProcessor {
+impl<E: LedgerEvents> TransactionProcessor<E> {
     #[cfg(feature = "multithreaded")]
     /// Returns an empty multi threaded [TransactionProcessor].
-    pub fn new(rx: Receiver<Transaction>) -> TransactionProcessor {
+    pub fn new(
+        rx: Receiver<(usize, Transaction)>,
+        policy: DisputePolicy,
+        events: E,
+        rejections: Option<RejectionReport>,
+    ) -> Self {
         TransactionProcessor {
             clients: Default::default(),
+            policy,
+            events,
+            rejections,
             rx,
         }
     }
     /// Returns an empty single threaded [TransactionProcessor].
     #[cfg(not(feature = "multithreaded"))]
-    pub fn new() -> TransactionProcessor {
+    pub fn new(policy: DisputePolicy, events: E, rejections: Option<RejectionReport>) -> Self {
         TransactionProcessor {
             clients: Default::default(),
+            registry: Default::default(),
+            policy,
+            events,
+            rejections,
         }
     }
     #[cfg(feature = "multithreaded")]
     /// Processes all transactions received through `rx` until `rx` closes.
     pub async fn run(&mut self) {
-        while let Some(tx) = self.rx.next().await {
-            self.process(tx)
+        while let Some((line, tx)) = self.rx.next().await {
+            self.process(line, tx)
         }
     }
-    /// Forwards `tx` to the appropriate client for processing.
-    pub fn process(&mut self, tx: Transaction) {
+    /// Forwards `tx`, found in `line` of the input, to the appropriate client for processing.
+    pub fn process(&mut self, line: usize, tx: Transaction) {
+        #[cfg(not(feature = "multithreaded"))]
+        if !self
+            .registry
+            .admit(line, &tx, &mut self.events, &mut self.rejections)
+        {
+            return;
+        }
+        // Only keep a copy around when it may have to be reported.
+        let copy = self.rejections.as_ref().map(|_| tx.clone());
+        let rejection = self.apply(tx);
+        if let (Some(rejections), Some(tx), Some(reason)) = (&mut self.rejections, copy, rejection)
+        {
+            rejections.record(line, &tx, reason)
+        }
+    }
+    /// Applies `tx` to its client, returns the reason why it was rejected if it was.
+    fn apply(&mut self, tx: Transaction) -> Option<Rejection> {
+        let mut events = TrackRejection {
+            events: &mut self.events,
+            rejection: None,
+        };
         self.clients
             .entry(tx.client())
             .or_default()
-            .process_transaction(tx)
+            .process_transaction(tx, self.policy, &mut events);
+        events.rejection
+    }
+    /// Flushes the event sink and the rejection report, returning the first error they encountered.
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.events.flush()?;
+        match &mut self.rejections {
+            Some(rejections) => rejections.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Forwards events to `events` and remembers the reason of the last rejection.
+struct TrackRejection<'a, E: LedgerEvents> {
+    events: &'a mut E,
+    rejection: Option<Rejection>,
+}
+impl<E: LedgerEvents> LedgerEvents for TrackRejection<'_, E> {
+    fn record(&mut self, event: LedgerEvent) {
+        if let Some(rejection) = event.rejection() {
+            self.rejection = Some(rejection)
+        }
+        self.events.record(event)
     }
 }
 
@@ -63,40 +197,303 @@ type IntoIter = Map<
     <FxHashMap<ClientId, ClientState> as IntoIterator>::IntoIter,
     fn((ClientId, ClientState)) -> Output,
 >;
-impl IntoIterator for TransactionProcessor {
+impl<E: LedgerEvents> IntoIterator for TransactionProcessor<E> {
     type IntoIter = IntoIter;
     type Item = Output;
     fn into_iter(self) -> Self::IntoIter {
         self.clients.into_iter().map(Into::into)
     }
 }
+impl<E: LedgerEvents> TransactionProcessor<E> {
+    /// Returns the [Outputs](Output) of every client in `order`.
+    pub fn into_outputs(self, order: OutputOrder) -> Outputs {
+        match order {
+            OutputOrder::Client => Outputs::Sorted {
+                clients: self.clients,
+                next: 0,
+            },
+            OutputOrder::Unsorted => Outputs::Unsorted(self.into_iter()),
+        }
+    }
+}
+
+/// Iterator over the [Outputs](Output) of a [TransactionProcessor], see [TransactionProcessor::into_outputs].
+enum Outputs {
+    Unsorted(IntoIter),
+    /// Client ids are only 16 bits so walking all of them in order and taking each client out of the map
+    /// sorts the clients without copying them anywhere else.
+    Sorted {
+        clients: FxHashMap<ClientId, ClientState>,
+        /// Next client id to look up.
+        next: u32,
+    },
+}
+impl Iterator for Outputs {
+    type Item = Output;
+    fn next(&mut self) -> Option<Output> {
+        match self {
+            Outputs::Unsorted(iter) => iter.next(),
+            Outputs::Sorted { clients, next } => {
+                while !clients.is_empty() && *next <= u32::from(u16::MAX) {
+                    let client = ClientId(*next as u16);
+                    *next += 1;
+                    if let Some(state) = clients.remove(&client) {
+                        return Some((client, state).into());
+                    }
+                }
+                None
+            }
+        }
+    }
+}
+
+/// Merges `shards`, each one sorted by client id, into a single iterator sorted by client id.
+#[cfg(any(test, feature = "multithreaded"))]
+fn merge_sorted(shards: Vec<impl Iterator<Item = Output>>) -> impl Iterator<Item = Output> {
+    let mut shards: Vec<_> = shards.into_iter().map(Iterator::peekable).collect();
+    std::iter::from_fn(move || {
+        // There is one shard per processor, a linear scan beats a heap at that size.
+        let (_, first) = shards
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(i, shard)| Some((shard.peek()?.client, i)))
+            .min()?;
+        shards[first].next()
+    })
+}
 
 // Tuned with time_multi.sh
 #[cfg(feature = "multithreaded")]
 /// Number of messages in the channels between tasks.
 const MESSAGE_BUFFER: usize = 100000;
 
+#[cfg(feature = "multithreaded")]
+/// Number of threads of the executor, must match the `threads` of main.
+pub const EXECUTOR_THREADS: usize = 2;
+
+#[derive(Debug, Clone, Default)]
+/// Options that change how [process] reads its input and writes its output.
+pub struct Options {
+    pub parse: ParseOptions,
+    pub disputes: DisputePolicy,
+    pub events: EventTarget,
+    /// Csv file where every rejected transaction is written, if any.
+    pub rejections: Option<PathBuf>,
+    /// Reads the input through a memory map instead of a buffered reader.
+    pub mmap: bool,
+    /// Format of the input.
+    pub format: InputFormat,
+    /// Format of the account states written to stdout.
+    pub output_format: OutputFormat,
+    /// Order of the account states written to stdout.
+    pub output_order: OutputOrder,
+    /// Columns of the account states written to stdout.
+    pub output_columns: OutputColumns,
+    /// File the account states are written to instead of stdout.
+    ///
+    /// It's only replaced once every account state has been written.
+    pub output: Option<PathBuf>,
+}
+
+/// Where [read_file] and the functions it calls send the transactions they read.
+#[derive(Debug, Clone, Copy)]
+enum Destination<'a> {
+    /// Processes them and outputs the resulting [Outputs](Output) to stdout.
+    Process,
+    /// Writes them to a binary log at the given path.
+    Convert(&'a str),
+}
+
 /// Processes the transactions in `file_in` and outputs the resulting [Outputs](Output) to stdout.
-pub async fn process(file_in: &str) -> Result<(), Box<dyn Error>> {
-    // Create a transaction stream.
+///
+/// `-` reads the transactions from stdin.
+pub async fn process(file_in: &str, options: &Options) -> Result<(), Box<dyn Error>> {
+    read_file(file_in, options, Destination::Process).await
+}
+
+/// Converts the transactions in `file_in` to a [binary log](binlog) at `file_out`,
+/// which [process] replays without parsing text.
+///
+/// The input is read like in [process], invalid records are handled according to [ParseOptions::on_error].
+pub async fn convert(
+    file_in: &str,
+    file_out: &str,
+    options: &Options,
+) -> Result<(), Box<dyn Error>> {
+    read_file(file_in, options, Destination::Convert(file_out)).await
+}
+
+/// Reads the transactions in `file_in` and sends them to `destination`.
+async fn read_file(
+    file_in: &str,
+    options: &Options,
+    destination: Destination<'_>,
+) -> Result<(), Box<dyn Error>> {
+    if file_in == "-" {
+        let read = BufReader::new(Unblock::new(std::io::stdin()));
+        return read_stream(read, options, destination).await;
+    }
+    if options.mmap {
+        let file = std::fs::File::open(file_in)?;
+        // Safety: the input must not be modified while it's being processed,
+        // which is also required to get meaningful results out of it.
+        let map = unsafe { Mmap::map(&file)? };
+        if Compression::detect(&map) != Compression::None
+            || map.starts_with(binlog::MAGIC)
+            || options.format != InputFormat::Csv
+        {
+            return read_stream(&map[..], options, destination).await;
+        }
+        #[cfg(not(feature = "multithreaded"))]
+        let transactions = parse(&map[..], options.parse.clone());
+        // Every cpu parses a slice of the map, the dispatcher gets them back in order.
+        #[cfg(feature = "multithreaded")]
+        let transactions = parse_mapped(map, options.parse.clone(), num_cpus::get());
+        return consume(transactions, options, destination).await;
+    }
     let file = File::open(file_in).await?;
     // Bigger buffer shaves a few milliseconds.
-    let mut transactions = parse(BufReader::with_capacity(100 * 1024, file));
+    read_stream(
+        BufReader::with_capacity(100 * 1024, file),
+        options,
+        destination,
+    )
+    .await
+}
+
+/// Reads the transactions in `read` and sends them to `destination`.
+///
+/// Gzip and zstd compressed inputs and [binary logs](binlog) are detected by their first bytes,
+/// [Options::mmap] is ignored.
+async fn read_stream(
+    read: impl AsyncBufRead + Unpin,
+    options: &Options,
+    destination: Destination<'_>,
+) -> Result<(), Box<dyn Error>> {
+    let (head, read) = peek(read, Compression::DETECT_LEN).await?;
+    match Compression::detect(&head) {
+        Compression::None => read_decompressed(read, options, destination).await,
+        Compression::Gzip => {
+            let read = BufReader::with_capacity(100 * 1024, gzip_decoder(read));
+            read_decompressed(read, options, destination).await
+        }
+        Compression::Zstd => {
+            let read = BufReader::with_capacity(100 * 1024, zstd_decoder(read));
+            read_decompressed(read, options, destination).await
+        }
+    }
+}
+
+/// Same as [read_stream] for an uncompressed `read`.
+///
+/// Binary logs are detected by their first bytes whatever [Options::format] says.
+async fn read_decompressed(
+    read: impl AsyncBufRead + Unpin,
+    options: &Options,
+    destination: Destination<'_>,
+) -> Result<(), Box<dyn Error>> {
+    let (head, read) = peek(read, binlog::MAGIC.len()).await?;
+    if head == binlog::MAGIC {
+        return consume(binlog::decode(read), options, destination).await;
+    }
+    if options.format == InputFormat::Jsonl {
+        let transactions = parse_jsonl(read, options.parse.clone());
+        return consume(transactions, options, destination).await;
+    }
+    #[cfg(not(feature = "multithreaded"))]
+    let transactions = parse(read, options.parse.clone());
+    // Every cpu parses a block of lines, the dispatcher gets them back in order.
+    #[cfg(feature = "multithreaded")]
+    let transactions = parse_chunked(read, options.parse.clone(), num_cpus::get());
+    consume(transactions, options, destination).await
+}
+
+/// Reads the first `len` bytes of `read`, fewer only if `read` ends before,
+/// and returns them with a reader that yields them again followed by the rest of `read`.
+///
+/// Pipes can return less than `len` bytes at a time, so a single `fill_buf` may not hold the magic bytes of a format.
+async fn peek<R: AsyncBufRead + Unpin>(
+    mut read: R,
+    len: usize,
+) -> std::io::Result<(Vec<u8>, Chain<Cursor<Vec<u8>>, R>)> {
+    let mut head = vec![0; len];
+    let mut filled = 0;
+    while filled < len {
+        match read.read(&mut head[filled..]).await? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    head.truncate(filled);
+    Ok((head.clone(), Cursor::new(head).chain(read)))
+}
+
+/// Sends `transactions` to `destination`.
+async fn consume(
+    transactions: impl TryStream<Ok = (usize, Transaction), Error = ParseError>,
+    options: &Options,
+    destination: Destination<'_>,
+) -> Result<(), Box<dyn Error>> {
+    match destination {
+        Destination::Process => process_transactions(transactions, options).await,
+        Destination::Convert(file_out) => binlog::write(transactions, file_out).await,
+    }
+}
+
+/// Processes `transactions` and outputs the resulting [Outputs](Output) to stdout.
+async fn process_transactions(
+    transactions: impl TryStream<Ok = (usize, Transaction), Error = ParseError>,
+    options: &Options,
+) -> Result<(), Box<dyn Error>> {
+    match &options.events {
+        EventTarget::None => process_with_events(transactions, options, NoopEvents).await,
+        EventTarget::Stderr => process_with_events(transactions, options, StderrEvents).await,
+        EventTarget::File(path) => {
+            process_with_events(transactions, options, FileEvents::create(path)?).await
+        }
+    }
+}
+
+/// Same as [process_transactions] but reports the events to `events`.
+///
+/// In multi threaded mode every processor gets a clone of `events`.
+async fn process_with_events<E: LedgerEvents + Clone + Send + 'static>(
+    transactions: impl TryStream<Ok = (usize, Transaction), Error = ParseError>,
+    options: &Options,
+    events: E,
+) -> Result<(), Box<dyn Error>> {
+    let transactions = transactions.into_stream();
+    pin_mut!(transactions);
+
+    // Create the rejection report, if requested.
+    let rejections = match &options.rejections {
+        Some(path) => Some(RejectionReport::create(path)?),
+        None => None,
+    };
 
-    // Create an output writer.
-    let mut wri = csv_async::AsyncSerializer::from_writer(Unblock::new(std::io::stdout()));
+    // Create an output writer, for the output file if there is one.
+    let mut output = match &options.output {
+        Some(path) => Some(PendingFile::create(path).await?),
+        None => None,
+    };
+    let sink: Box<dyn AsyncWrite + Unpin + '_> = match &mut output {
+        Some(file) => Box::new(file),
+        None => Box::new(Unblock::new(std::io::stdout())),
+    };
+    let mut wri = OutputWriter::new(sink, options.output_format, options.output_columns);
 
     #[cfg(not(feature = "multithreaded"))]
     {
         // Process each transaction.
-        let mut tp = TransactionProcessor::new();
-        while let Some(transaction) = transactions.try_next().await? {
-            tp.process(transaction)
+        let mut tp = TransactionProcessor::new(options.disputes, events, rejections);
+        while let Some((line, transaction)) = transactions.try_next().await? {
+            tp.process(line, transaction)
         }
+        tp.flush()?;
 
         // Output to stdout.
-        for output in tp {
-            wri.serialize(output).await?
+        for output in tp.into_outputs(options.output_order) {
+            wri.write(&output).await?
         }
     }
 
@@ -104,11 +501,15 @@ pub async fn process(file_in: &str) -> Result<(), Box<dyn Error>> {
     // If there are many chargebacks the processing step basically becomes only a hashmap lookup.
     #[cfg(feature = "multithreaded")]
     {
-        //let cpus = num_cpus::get() - 2;
-        // Having only one extra cpu is better than all available threads.
-        // Makes sense, one thread parses and the other processes, once you get more it's not worth the channel overhead.
-        // Flamegraph corroborates this.
-        let cpus = 1;
+        // Parsing used to take one thread and only left room for one processor,
+        // now it runs on the blocking pool so there is one processor per executor thread.
+        let cpus = EXECUTOR_THREADS;
+        // Duplicate ids are checked here so they are detected across every processor.
+        let mut registry = TransactionRegistry::default();
+        let mut events = events;
+        // Every task records its rejections in a shard, the report writes them in line order once all are done.
+        let mut report = rejections;
+        let mut rejections = report.as_ref().map(RejectionReport::shard);
 
         let mut txs = Vec::new();
         let mut tasks = Vec::new();
@@ -118,23 +519,31 @@ pub async fn process(file_in: &str) -> Result<(), Box<dyn Error>> {
 
         // Create a processor per cpu and a channel to send transactions to it.
         for _ in 0..cpus {
-            let (tx, rx) = bounded::<Transaction>(MESSAGE_BUFFER);
+            let (tx, rx) = bounded::<(usize, Transaction)>(MESSAGE_BUFFER);
 
             txs.push(tx);
 
+            let disputes = options.disputes;
+            let order = options.output_order;
+            let events = events.clone();
+            let rejections = report.as_ref().map(RejectionReport::shard);
             tasks.push(spawn(async move {
-                let mut tp = TransactionProcessor::new(rx);
+                let mut tp = TransactionProcessor::new(rx, disputes, events, rejections);
                 tp.run().await;
+                tp.flush()?;
                 // Once finished, the processor will return an iter of outputs.
-                tp.into_iter()
+                Ok::<_, std::io::Error>(tp.into_outputs(order))
             }))
         }
 
-        while let Some(transaction) = transactions.try_next().await? {
+        while let Some((line, transaction)) = transactions.try_next().await? {
+            if !registry.admit(line, &transaction, &mut events, &mut rejections) {
+                continue;
+            }
             // Transactions are partitioned by client id, assuming there is a uniform
             // distribution of client ids, this should be very efficient.
             txs[transaction.client().0 as usize % cpus]
-                .send(transaction)
+                .send((line, transaction))
                 .await?;
         }
 
@@ -142,17 +551,369 @@ pub async fn process(file_in: &str) -> Result<(), Box<dyn Error>> {
         for tx in txs {
             tx.close();
         }
+        events.flush()?;
+        if let Some(rejections) = &mut rejections {
+            rejections.flush()?;
+        }
 
-        // For each processor output the Output iter to stdout.
-        // This could be more efficient with a way to select! on a slice but
-        // I didn't find any good implementation and the output phase is not
-        // even visible in the flamegraph so it's probably not necessary.
+        // Every processor returns its clients in the requested order,
+        // sorted shards are merged so the output is the same as in single threaded mode.
+        let mut shards = Vec::with_capacity(cpus);
         for task in tasks {
-            for output in task.await {
-                wri.serialize(output).await?
-            }
+            shards.push(task.await?);
+        }
+        if let Some(report) = &mut report {
+            report.flush()?;
         }
+        let outputs: Box<dyn Iterator<Item = Output>> = match options.output_order {
+            OutputOrder::Client => Box::new(merge_sorted(shards)),
+            OutputOrder::Unsorted => Box::new(shards.into_iter().flatten()),
+        };
+        for output in outputs {
+            wri.write(&output).await?
+        }
+    }
+    wri.finish().await?;
+    // Errors above leave the previous output file untouched.
+    if let Some(file) = output {
+        file.commit().await?;
     }
 
     Ok(())
 }
+
+// The tests that drive a TransactionProcessor directly only build in single threaded mode,
+// where it isn't fed by a channel.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(not(feature = "multithreaded"))]
+    use crate::{amount::Amount, events::MemoryEvents};
+    use async_compression::futures::write::ZstdEncoder;
+    use futures::AsyncWriteExt;
+
+    #[cfg(not(feature = "multithreaded"))]
+    fn amount(s: &str) -> Amount {
+        s.parse().unwrap()
+    }
+
+    #[cfg(not(feature = "multithreaded"))]
+    #[test]
+    fn test_duplicate_transactions() {
+        let mut tp =
+            TransactionProcessor::new(DisputePolicy::default(), MemoryEvents::default(), None);
+
+        tp.process(
+            2,
+            Transaction::Deposit {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: amount("3"),
+            },
+        );
+        // Replayed deposit.
+        tp.process(
+            3,
+            Transaction::Deposit {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: amount("3"),
+            },
+        );
+        // Same id from another client.
+        tp.process(
+            4,
+            Transaction::Deposit {
+                client: ClientId(2),
+                tx: TransactionId(1),
+                amount: amount("5"),
+            },
+        );
+        // Withdrawal reusing a deposit id.
+        tp.process(
+            5,
+            Transaction::Withdrawal {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: amount("1"),
+            },
+        );
+        tp.process(
+            6,
+            Transaction::Withdrawal {
+                client: ClientId(1),
+                tx: TransactionId(2),
+                amount: amount("1"),
+            },
+        );
+        // Replayed withdrawal.
+        tp.process(
+            7,
+            Transaction::Withdrawal {
+                client: ClientId(1),
+                tx: TransactionId(2),
+                amount: amount("1"),
+            },
+        );
+        // Disputes refer to existing ids so they are not duplicates.
+        tp.process(
+            8,
+            Transaction::Dispute {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: None,
+            },
+        );
+        // Administrative transactions share the ids of deposits and withdrawals.
+        tp.process(
+            9,
+            Transaction::Freeze {
+                client: ClientId(1),
+                tx: TransactionId(2),
+                audit: None,
+            },
+        );
+        tp.process(
+            10,
+            Transaction::Freeze {
+                client: ClientId(1),
+                tx: TransactionId(3),
+                audit: None,
+            },
+        );
+        // Replayed freeze.
+        tp.process(
+            11,
+            Transaction::Freeze {
+                client: ClientId(1),
+                tx: TransactionId(3),
+                audit: None,
+            },
+        );
+        // Unlock reusing the id of the freeze.
+        tp.process(
+            12,
+            Transaction::Unlock {
+                client: ClientId(1),
+                tx: TransactionId(3),
+                audit: None,
+            },
+        );
+        tp.process(
+            13,
+            Transaction::Unlock {
+                client: ClientId(1),
+                tx: TransactionId(4),
+                audit: None,
+            },
+        );
+        // Deposit reusing the id of the unlock.
+        tp.process(
+            14,
+            Transaction::Deposit {
+                client: ClientId(1),
+                tx: TransactionId(4),
+                amount: amount("1"),
+            },
+        );
+
+        let duplicates = tp
+            .events
+            .events
+            .iter()
+            .filter(|event| matches!(event, LedgerEvent::DuplicateTransaction(_)))
+            .count();
+        assert_eq!(duplicates, 8);
+
+        let outputs: Vec<_> = tp.into_iter().collect();
+        assert_eq!(outputs.len(), 1);
+        let output = &outputs[0];
+        assert_eq!(output.client, ClientId(1));
+        assert_eq!(output.available, amount("-1"));
+        assert_eq!(output.held, amount("3"));
+        assert!(!output.locked);
+    }
+
+    #[cfg(not(feature = "multithreaded"))]
+    #[test]
+    fn test_output_order() {
+        let mut tp = TransactionProcessor::new(DisputePolicy::default(), NoopEvents, None);
+        let ids = [300, 7, u16::MAX, 0, 42, 1000, 1];
+        for (i, &client) in ids.iter().enumerate() {
+            tp.process(
+                i + 2,
+                Transaction::Deposit {
+                    client: ClientId(client),
+                    tx: TransactionId(i as u32),
+                    amount: amount("1"),
+                },
+            )
+        }
+        let clients: Vec<_> = tp
+            .into_outputs(OutputOrder::Client)
+            .map(|output| output.client.0)
+            .collect();
+        assert_eq!(clients, vec![0, 1, 7, 42, 300, 1000, u16::MAX]);
+
+        // Shards partitioned by client id like the multi threaded processors.
+        let mut shards: Vec<_> = (0..3)
+            .map(|_| TransactionProcessor::new(DisputePolicy::default(), NoopEvents, None))
+            .collect();
+        for (i, &client) in ids.iter().enumerate() {
+            shards[client as usize % 3].process(
+                i + 2,
+                Transaction::Deposit {
+                    client: ClientId(client),
+                    tx: TransactionId(i as u32),
+                    amount: amount("1"),
+                },
+            )
+        }
+        let shards = shards
+            .into_iter()
+            .map(|tp| tp.into_outputs(OutputOrder::Client))
+            .collect();
+        let clients: Vec<_> = merge_sorted(shards).map(|output| output.client.0).collect();
+        assert_eq!(clients, vec![0, 1, 7, 42, 300, 1000, u16::MAX]);
+    }
+
+    #[smol_potat::test]
+    async fn test_read_stream() {
+        let path = std::env::temp_dir().join(format!("process-read-{}.csv", std::process::id()));
+        let options = Options {
+            output: Some(path.clone()),
+            ..Default::default()
+        };
+        let input = "type,client,tx,amount\ndeposit,2,1,1.5\ndeposit,1,2,2\nwithdrawal,2,3,0.5\n";
+        let expected = "client,available,held,total,locked\n\
+                        1,2.0000,0.0000,2.0000,false\n\
+                        2,1.0000,0.0000,1.0000,false\n";
+        read_stream(input.as_bytes(), &options, Destination::Process)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), expected);
+
+        // Compressed inputs and binary logs are detected even if every read returns a single byte, like a slow pipe.
+        let mut zstd = ZstdEncoder::new(Vec::new());
+        zstd.write_all(input.as_bytes()).await.unwrap();
+        zstd.close().await.unwrap();
+        let mut log = Vec::new();
+        let mut encoder = binlog::Encoder::default();
+        encoder.header(&mut log);
+        let transactions: Vec<_> = crate::io::parse(input.as_bytes(), Default::default())
+            .try_collect()
+            .await
+            .unwrap();
+        for (line, tx) in &transactions {
+            encoder.encode(*line, tx, &mut log).unwrap();
+        }
+        for bytes in [zstd.into_inner(), log] {
+            std::fs::remove_file(&path).unwrap();
+            let read = BufReader::with_capacity(1, &bytes[..]);
+            read_stream(read, &options, Destination::Process)
+                .await
+                .unwrap();
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), expected);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[smol_potat::test]
+    async fn test_rejections_in_line_order() {
+        let dir = std::env::temp_dir();
+        let output = dir.join(format!("ordered-{}.csv", std::process::id()));
+        let rejections = dir.join(format!("ordered-rejections-{}.csv", std::process::id()));
+        let options = Options {
+            output: Some(output.clone()),
+            rejections: Some(rejections.clone()),
+            ..Default::default()
+        };
+        // Duplicates are rejected before the transactions are handed to a processor,
+        // overdrafts by the processor of the client, every client has a different one.
+        let mut input = String::from("type,client,tx,amount\n");
+        let mut expected = String::from("line,transaction,reason\n");
+        for i in 0..3000 {
+            let (line, client) = (i + 2, i % 7);
+            match i % 3 {
+                0 => {
+                    input.push_str(&format!("withdrawal,{},{},1000000\n", client, 100_000 + i));
+                    expected.push_str(&format!(
+                        "{},\"withdrawal,{},{},1000000\",insufficient_funds\n",
+                        line,
+                        client,
+                        100_000 + i
+                    ));
+                }
+                1 => input.push_str(&format!("deposit,{},{},1\n", client, i)),
+                _ => {
+                    input.push_str(&format!("deposit,{},{},1\n", client, i - 1));
+                    expected.push_str(&format!(
+                        "{},\"deposit,{},{},1\",duplicate_transaction\n",
+                        line,
+                        client,
+                        i - 1
+                    ));
+                }
+            }
+        }
+        read_stream(input.as_bytes(), &options, Destination::Process)
+            .await
+            .unwrap();
+        let written = std::fs::read_to_string(&rejections).unwrap();
+        std::fs::remove_file(&output).unwrap();
+        std::fs::remove_file(&rejections).unwrap();
+        assert_eq!(written, expected);
+    }
+
+    #[cfg(not(feature = "multithreaded"))]
+    #[test]
+    fn test_rejection_report() {
+        let path = std::env::temp_dir().join(format!("report-{}.csv", std::process::id()));
+        let report = RejectionReport::create(&path).unwrap();
+        let mut tp = TransactionProcessor::new(DisputePolicy::default(), NoopEvents, Some(report));
+
+        for (line, tx) in vec![
+            Transaction::Deposit {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: amount("3"),
+            },
+            Transaction::Withdrawal {
+                client: ClientId(1),
+                tx: TransactionId(2),
+                amount: amount("5"),
+            },
+            Transaction::Deposit {
+                client: ClientId(2),
+                tx: TransactionId(1),
+                amount: amount("1"),
+            },
+            Transaction::Resolve {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: None,
+            },
+            Transaction::Dispute {
+                client: ClientId(1),
+                tx: TransactionId(1),
+                amount: None,
+            },
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            tp.process(line + 2, tx)
+        }
+        tp.flush().unwrap();
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            written,
+            "line,transaction,reason\n\
+             3,\"withdrawal,1,2,5\",insufficient_funds\n\
+             4,\"deposit,2,1,1\",duplicate_transaction\n\
+             5,\"resolve,1,1,\",not_disputed\n"
+        );
+    }
+}
@@ -1,2 +1,2 @@
 cargo build --release
-perf stat -B -e cache-references,cache-misses,cycles,instructions,branches,branch-misses target/release/transaction_processor inputs/big/random.csv >result.csv
+perf stat -B -e cache-references,cache-misses,cycles,instructions,branches,branch-misses target/release/transaction_processor inputs/big/random.csv --output result.csv